@@ -0,0 +1,189 @@
+use std::ops::{Add, Mul, Sub};
+
+use crate::vec3::Point3;
+
+// Camera parameters at a frame, vfov drives perspective projections
+#[derive(Copy, Clone)]
+pub struct Keyframe {
+    pub frame: f64,
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vfov: f64,
+    pub focus_dist: f64,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    // Passes through every keyframe with a continuous velocity
+    CatmullRom,
+}
+
+pub struct CameraAnimation {
+    keyframes: Vec<Keyframe>,
+    interpolation: Interpolation,
+}
+
+impl CameraAnimation {
+    pub fn new(mut keyframes: Vec<Keyframe>, interpolation: Interpolation) -> Self {
+        assert!(!keyframes.is_empty(), "camera animation without keyframes");
+        keyframes.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+        // Of keyframes at the same frame the last one given wins
+        keyframes.dedup_by(|later, kept| {
+            let same_frame = later.frame == kept.frame;
+            if same_frame {
+                *kept = *later;
+            }
+            same_frame
+        });
+
+        Self {
+            keyframes,
+            interpolation,
+        }
+    }
+
+    // Holds the first and last keyframes outside of their range
+    pub fn at(&self, frame: f64) -> Keyframe {
+        let keys = &self.keyframes;
+        let last = keys.len() - 1;
+
+        let i = keys.partition_point(|k| k.frame <= frame);
+        if i == 0 {
+            return keys[0];
+        }
+        if i > last {
+            return keys[last];
+        }
+
+        let (k1, k2) = (&keys[i - 1], &keys[i]);
+        let t = (frame - k1.frame) / (k2.frame - k1.frame);
+
+        // The end keyframes are repeated for the outer tangents
+        let k0 = &keys[i.saturating_sub(2)];
+        let k3 = &keys[(i + 1).min(last)];
+        let frames = [k0.frame, k1.frame, k2.frame, k3.frame];
+
+        let interpolate = |f: &dyn Fn(&Keyframe) -> Point3| match self.interpolation {
+            Interpolation::Linear => lerp(f(k1), f(k2), t),
+            Interpolation::CatmullRom => catmull_rom([f(k0), f(k1), f(k2), f(k3)], frames, t),
+        };
+        let interpolate_f64 = |f: &dyn Fn(&Keyframe) -> f64| match self.interpolation {
+            Interpolation::Linear => lerp(f(k1), f(k2), t),
+            Interpolation::CatmullRom => catmull_rom([f(k0), f(k1), f(k2), f(k3)], frames, t),
+        };
+
+        Keyframe {
+            frame,
+            lookfrom: interpolate(&|k| k.lookfrom),
+            lookat: interpolate(&|k| k.lookat),
+            vfov: interpolate_f64(&|k| k.vfov),
+            focus_dist: interpolate_f64(&|k| k.focus_dist),
+        }
+    }
+}
+
+fn lerp<T>(a: T, b: T, t: f64) -> T
+where
+    T: Add<Output = T> + Mul<f64, Output = T>,
+{
+    a * (1.0 - t) + b * t
+}
+
+// Catmull-Rom between p1 and p2 at their keyframes' frames. The tangents are differences over
+// the neighbouring keyframes' frame span, scaled to the segment's, so unevenly spaced
+// keyframes don't make the velocity jump
+fn catmull_rom<T>([p0, p1, p2, p3]: [T; 4], [f0, f1, f2, f3]: [f64; 4], t: f64) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>,
+{
+    let d = f2 - f1;
+    let m1 = (p2 - p0) * (d / (f2 - f0));
+    let m2 = (p3 - p1) * (d / (f3 - f1));
+    let (t2, t3) = (t * t, t * t * t);
+
+    p1 * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + m1 * (t3 - 2.0 * t2 + t)
+        + p2 * (3.0 * t2 - 2.0 * t3)
+        + m2 * (t3 - t2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(frame: f64, x: f64) -> Keyframe {
+        Keyframe {
+            frame,
+            lookfrom: Point3::new(x, 0.0, 0.0),
+            lookat: Point3::new(0.0, 0.0, 0.0),
+            vfov: 20.0 + x,
+            focus_dist: 10.0,
+        }
+    }
+
+    #[test]
+    fn passes_through_keyframes() {
+        let keys = vec![
+            key(0.0, 0.0),
+            key(10.0, 4.0),
+            key(20.0, 1.0),
+            key(40.0, 3.0),
+        ];
+
+        for interpolation in [Interpolation::Linear, Interpolation::CatmullRom] {
+            let animation = CameraAnimation::new(keys.clone(), interpolation);
+            for k in &keys {
+                let at = animation.at(k.frame);
+                assert!((at.lookfrom.x() - k.lookfrom.x()).abs() < 1e-12);
+                assert!((at.vfov - k.vfov).abs() < 1e-12);
+            }
+            assert_eq!(animation.at(-5.0).lookfrom.x(), 0.0);
+            assert_eq!(animation.at(50.0).lookfrom.x(), 3.0);
+        }
+
+        let linear = CameraAnimation::new(keys, Interpolation::Linear);
+        assert!((linear.at(5.0).lookfrom.x() - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn continuous_velocity_at_uneven_keyframes() {
+        let keys = vec![
+            key(0.0, 0.0),
+            key(10.0, 4.0),
+            key(20.0, 1.0),
+            key(40.0, 3.0),
+        ];
+        let animation = CameraAnimation::new(keys, Interpolation::CatmullRom);
+
+        let h = 1e-4;
+        let x = |frame| animation.at(frame).lookfrom.x();
+        for frame in [10.0, 20.0] {
+            let before = (x(frame) - x(frame - h)) / h;
+            let after = (x(frame + h) - x(frame)) / h;
+            assert!(
+                (before - after).abs() < 1e-3,
+                "{frame}: {before} != {after}"
+            );
+        }
+    }
+
+    #[test]
+    fn duplicate_keyframes_keep_the_last() {
+        let keys = vec![
+            key(0.0, 0.0),
+            key(10.0, 4.0),
+            key(10.0, 2.0),
+            key(20.0, 1.0),
+        ];
+
+        for interpolation in [Interpolation::Linear, Interpolation::CatmullRom] {
+            let animation = CameraAnimation::new(keys.clone(), interpolation);
+            assert_eq!(animation.at(10.0).lookfrom.x(), 2.0);
+            // No jump from the first duplicate to the last
+            let before = animation.at(10.0 - 1e-9).lookfrom.x();
+            assert!((before - 2.0).abs() < 1e-6, "{before}");
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::utils::Color;
+use crate::vec3::{Point3, Vec3};
+
+// Auxiliary buffers rendered next to the beauty image, each written as its own image
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Aov {
+    // Attenuation of the first scattering event
+    Albedo,
+    // First hit shading normal facing the camera, in world space
+    Normal,
+    // Distance from the camera to the first hit, 0 for the background
+    Depth,
+    Position,
+    // Ids are shown as random colors, 0 is the background and black
+    ObjectId,
+    MaterialId,
+    // Light reaching the camera after at most one scattering event, the background included
+    Direct,
+    Indirect,
+}
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+}
+
+pub struct FirstHit {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub position: Point3,
+    pub distance: f64,
+    pub object_id: u32,
+    pub material_id: u32,
+}
+
+// Radiance carried by a camera ray along with what it first hit
+#[derive(Default)]
+pub struct PathSample {
+    pub color: Color,
+    pub direct: Color,
+    pub first_hit: Option<FirstHit>,
+}
+
+impl PathSample {
+    // Scales the radiance, what was hit stays the same
+    pub fn weighted(mut self, weight: f64) -> Self {
+        self.color = weight * self.color;
+        self.direct = weight * self.direct;
+        self
+    }
+
+    pub fn aov(&self, aov: Aov) -> Color {
+        if let Aov::Direct = aov {
+            return self.direct;
+        }
+        if let Aov::Indirect = aov {
+            return self.color - self.direct;
+        }
+
+        let Some(hit) = &self.first_hit else {
+            return Color::default();
+        };
+        match aov {
+            Aov::Albedo => hit.albedo,
+            Aov::Normal => hit.normal,
+            Aov::Depth => Color::new(hit.distance, hit.distance, hit.distance),
+            Aov::Position => hit.position,
+            Aov::ObjectId => id_color(hit.object_id),
+            Aov::MaterialId => id_color(hit.material_id),
+            Aov::Direct | Aov::Indirect => unreachable!(),
+        }
+    }
+}
+
+// Stable colors for ids, consecutive ids are spread around the hue circle by the golden ratio
+pub(crate) fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::default();
+    }
+
+    let hue = (id as f64 * 0.618033988749895).fract() * 6.0;
+    let (saturation, value) = (0.75, 0.95);
+
+    // HSV to RGB
+    let channel = |n: f64| {
+        let k = (n + hue) % 6.0;
+        value - value * saturation * k.min(4.0 - k).clamp(0.0, 1.0)
+    };
+    Color::new(channel(5.0), channel(3.0), channel(1.0))
+}
@@ -0,0 +1,116 @@
+use std::f64::consts::PI;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+
+use crate::image::Image;
+use crate::sampler;
+use crate::utils;
+use crate::vec3::Vec3;
+
+// Shape of the lens opening, which out of focus highlights take on
+#[derive(Clone, Default)]
+pub enum Aperture {
+    #[default]
+    Disk,
+    // Regular polygon inscribed in the disk, rotation is in degrees
+    Polygon {
+        blades: u32,
+        rotation: f64,
+    },
+    Mask(Arc<ApertureMask>),
+}
+
+impl Aperture {
+    // Point on the aperture, within the unit disk or for masks the [-1, 1] square
+    pub fn sample(&self) -> Vec3 {
+        match self {
+            Aperture::Disk => Vec3::rand_in_unit_disk(),
+            Aperture::Polygon { blades, rotation } => {
+                sample_polygon((*blades).max(3), utils::deg_to_rad(*rotation))
+            }
+            Aperture::Mask(mask) => mask.sample(),
+        }
+    }
+}
+
+// Picks one of the polygon's triangles, all of equal area, then a uniform point in it
+fn sample_polygon(blades: u32, rotation: f64) -> Vec3 {
+    let (u1, u2) = sampler::get_2d();
+
+    let sector = u1 * blades as f64;
+    let k = (sector as u32).min(blades - 1);
+    let u1 = sector - k as f64;
+
+    let vertex = |i: u32| {
+        let angle = rotation + 2.0 * PI * i as f64 / blades as f64;
+        Vec3::new(angle.cos(), angle.sin(), 0.0)
+    };
+
+    let r = u1.sqrt();
+    r * ((1.0 - u2) * vertex(k) + u2 * vertex(k + 1))
+}
+
+// An image of the aperture's transmission covering the [-1, 1] square, sampled
+// proportionally to its luminance. Only the shape matters, exposure stays the same
+pub struct ApertureMask {
+    width: u32,
+    height: u32,
+    // Running sum of the pixel weights in row order, normalized to end at 1
+    cdf: Vec<f64>,
+}
+
+impl ApertureMask {
+    // Fails on an all black image, which lets no light through
+    pub fn new(image: &Image) -> io::Result<Self> {
+        let (width, height) = (image.width(), image.height());
+
+        let mut total = 0.0;
+        let mut cdf: Vec<f64> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                total += utils::luminance(image.get(x, y)).max(0.0);
+                total
+            })
+            .collect();
+
+        if total <= 0.0 {
+            return Err(Error::new(ErrorKind::InvalidData, "aperture mask is black"));
+        }
+        cdf.iter_mut().for_each(|c| *c /= total);
+
+        Ok(Self { width, height, cdf })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub(crate) fn cdf(&self) -> &[f64] {
+        &self.cdf
+    }
+
+    fn sample(&self) -> Vec3 {
+        let (u1, u2) = sampler::get_2d();
+
+        let idx = self
+            .cdf
+            .partition_point(|&c| c <= u1)
+            .min(self.cdf.len() - 1);
+        let lower = if idx == 0 { 0.0 } else { self.cdf[idx - 1] };
+
+        // Reuse where u1 fell in the pixel's cdf interval for the horizontal offset
+        let du = (u1 - lower) / (self.cdf[idx] - lower);
+        let x = (idx as u32 % self.width) as f64 + du.clamp(0.0, 1.0);
+        let y = (idx as u32 / self.width) as f64 + u2;
+
+        Vec3::new(
+            2.0 * x / self.width as f64 - 1.0,
+            1.0 - 2.0 * y / self.height as f64,
+            0.0,
+        )
+    }
+}
@@ -0,0 +1,478 @@
+use std::f64::consts::PI;
+use std::io;
+use std::sync::Arc;
+
+use crate::aperture::Aperture;
+use crate::lens::LensSystem;
+use crate::ray::Ray;
+use crate::raytracer::RenderConfig;
+use crate::sampler;
+use crate::utils;
+use crate::vec3::{Point3, Vec3};
+
+// How film positions map to camera rays, angles are in degrees
+#[derive(Clone, Debug)]
+pub enum Projection {
+    // Thin lens, vfov is the vertical field of view
+    Perspective {
+        vfov: f64,
+    },
+    // Parallel rays, height is the vertical extent of the view in world units
+    Orthographic {
+        height: f64,
+    },
+    // Equidistant, the image circle fits the shorter side and spans fov
+    Fisheye {
+        fov: f64,
+    },
+    // Full sphere, longitude across and latitude down the image, 2:1 images are undistorted
+    Equirectangular,
+    // 3 x 2 faces of 90 degrees each: right, left, up / down, front, back
+    Cubemap,
+    // Traced through the lens elements onto a film with the given diagonal in mm, focused
+    // at the camera's focus distance in meters. The stop replaces the aperture settings
+    Realistic {
+        lens: Arc<LensSystem>,
+        film_diagonal: f64,
+    },
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Perspective { vfov: 20.0 }
+    }
+}
+
+// Placement and focus, distances are in world units and the defocus angle in degrees
+#[derive(Copy, Clone)]
+pub struct CameraSettings {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub focus_dist: f64,
+    pub defocus_angle: f64,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            lookfrom: Point3::new(13.0, 2.0, 3.0),
+            lookat: Point3::new(0.0, 0.0, 0.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            focus_dist: 10.0,
+            defocus_angle: 0.6,
+        }
+    }
+}
+
+// Left and right eyes share the image, each eye gets half of it. Perspective views converge
+// by shifting the eyes' viewports, the zero parallax plane is at convergence distance.
+// Panoramic projections use omni-directional stereo, every ray's eye sits on a circle of
+// diameter ipd, shrinking towards the poles to avoid swapped eyes when looking up or down
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Stereo {
+    pub ipd: f64,
+    // Infinite for parallel eyes
+    pub convergence: f64,
+    pub layout: StereoLayout,
+}
+
+// The left eye goes left or on top
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum StereoLayout {
+    #[default]
+    SideBySide,
+    TopBottom,
+}
+
+impl Default for Stereo {
+    fn default() -> Self {
+        Self {
+            ipd: 0.064,
+            convergence: f64::INFINITY,
+            layout: StereoLayout::default(),
+        }
+    }
+}
+
+impl Stereo {
+    fn eye_size(&self, (width, height): (f64, f64)) -> (f64, f64) {
+        match self.layout {
+            StereoLayout::SideBySide => (width / 2.0, height),
+            StereoLayout::TopBottom => (width, height / 2.0),
+        }
+    }
+
+    // Film position within the eye's view and the eye's offset to the right of the center
+    fn split(&self, x: f64, y: f64, (eye_width, eye_height): (f64, f64)) -> (f64, f64, f64) {
+        let (x, y, right_eye) = match self.layout {
+            StereoLayout::SideBySide if x >= eye_width => (x - eye_width, y, true),
+            StereoLayout::TopBottom if y >= eye_height => (x, y - eye_height, true),
+            _ => (x, y, false),
+        };
+
+        let offset = if right_eye { self.ipd } else { -self.ipd } / 2.0;
+        (x, y, offset)
+    }
+}
+
+pub struct Camera {
+    center: Point3,
+    // Right, up and backwards
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    projection: Projection,
+    stereo: Option<Stereo>,
+    // Of a single eye in stereo
+    image_size: (f64, f64),
+    pixel00_loc: Point3,
+    pixel_du: Vec3,
+    pixel_dv: Vec3,
+    defocus_disk_u: Vec3,
+    defocus_disk_v: Vec3,
+    defocus_angle: f64,
+    focus_dist: f64,
+    aperture: Aperture,
+    cat_eye: f64,
+    // Focused copy of the realistic projection's lens
+    lens: Option<LensSystem>,
+}
+
+impl Camera {
+    // Fails when the realistic projection's lens can't focus at the focus distance
+    pub fn new(config: &RenderConfig) -> io::Result<Self> {
+        let resolution = (config.resolution.0 as f64, config.resolution.1 as f64);
+        let (image_width, image_height) = match config.stereo {
+            Some(stereo) => stereo.eye_size(resolution),
+            None => resolution,
+        };
+
+        let CameraSettings {
+            lookfrom,
+            lookat,
+            vup,
+            focus_dist,
+            defocus_angle,
+        } = config.camera;
+
+        // Only the planar projections use the viewport
+        let viewport_height = match &config.projection {
+            Projection::Perspective { vfov } => {
+                let theta = utils::deg_to_rad(*vfov);
+                2.0 * f64::tan(theta / 2.0) * focus_dist
+            }
+            Projection::Orthographic { height } => *height,
+            _ => 0.0,
+        };
+        let viewport_width = viewport_height * (image_width / image_height);
+
+        let center = lookfrom;
+
+        let w = (lookfrom - lookat).unit();
+        let u = vup.cross(&w).unit();
+        let v = w.cross(&u).unit();
+
+        let viewport_u = viewport_width * u;
+        let viewport_v = viewport_height * -v;
+
+        let pixel_du = viewport_u / image_width;
+        let pixel_dv = viewport_v / image_height;
+
+        let viewport_upper_left = center - (focus_dist * w) - (viewport_u + viewport_v) / 2.0;
+        let pixel00_loc = viewport_upper_left + (pixel_du + pixel_dv) / 2.0;
+
+        let defocus_radius = focus_dist * f64::tan(utils::deg_to_rad(defocus_angle / 2.0));
+        let defocus_disk_u = u * defocus_radius;
+        let defocus_disk_v = v * defocus_radius;
+
+        let lens = match &config.projection {
+            Projection::Realistic {
+                lens,
+                film_diagonal,
+            } => {
+                let focused = lens
+                    .focused(focus_dist, film_diagonal * 0.001)
+                    .ok_or_else(|| {
+                        let msg = format!("lens can't focus at {focus_dist}");
+                        io::Error::new(io::ErrorKind::InvalidInput, msg)
+                    })?;
+                Some(focused)
+            }
+            _ => None,
+        };
+
+        Ok(Camera {
+            center,
+            u,
+            v,
+            w,
+            projection: config.projection.clone(),
+            stereo: config.stereo,
+            image_size: (image_width, image_height),
+            pixel00_loc,
+            pixel_du,
+            pixel_dv,
+            defocus_disk_u,
+            defocus_disk_v,
+            defocus_angle,
+            focus_dist,
+            aperture: config.aperture.clone(),
+            cat_eye: config.cat_eye,
+            lens,
+        })
+    }
+
+    // (x, y) is a continuous film position, pixel (i, j) covers [i, i + 1) x [j, j + 1).
+    // The ray's radiance is scaled by the weight, None where the projection doesn't cover
+    // the film or the lens blocks the ray
+    pub fn get_ray(&self, x: f64, y: f64) -> Option<(Ray, f64)> {
+        let (x, y, eye_offset) = match &self.stereo {
+            Some(stereo) => stereo.split(x, y, self.image_size),
+            None => (x, y, 0.0),
+        };
+        let eye = eye_offset * self.u;
+
+        let pixel_sample =
+            self.pixel00_loc + ((x - 0.5) * self.pixel_du) + ((y - 0.5) * self.pixel_dv);
+
+        let direction = match &self.projection {
+            Projection::Perspective { .. } => {
+                let ray_origin = if self.defocus_angle <= 0.0 {
+                    self.center + eye
+                } else {
+                    self.defocus_disk_sample(x, y)? + eye
+                };
+                // Where the eye's ray through the convergence plane crosses the focus plane
+                let target = pixel_sample + (1.0 - self.focus_dist / self.convergence()) * eye;
+                return Some((Ray::new(ray_origin, target - ray_origin), 1.0));
+            }
+            // From the camera plane, so nothing behind the camera shows up
+            Projection::Orthographic { .. } => {
+                let ray_origin = pixel_sample + self.focus_dist * self.w + eye;
+                return Some((Ray::new(ray_origin, -self.w), 1.0));
+            }
+            Projection::Fisheye { fov } => self.fisheye(x, y, *fov)?,
+            Projection::Equirectangular => self.equirectangular(x, y),
+            Projection::Cubemap => self.cubemap(x, y),
+            Projection::Realistic { film_diagonal, .. } => {
+                let (ray, weight) = self.realistic(x, y, film_diagonal * 0.001)?;
+                let ray = Ray::new(ray.origin() + eye, ray.direction());
+                return Some((ray, weight));
+            }
+        };
+
+        let direction = direction.unit();
+        if eye_offset == 0.0 {
+            return Some((Ray::new(self.center, direction), 1.0));
+        }
+
+        // Omni-directional stereo, the eye is offset to the right of the horizontal direction
+        let right = eye_offset * direction.cross(&self.v);
+        let ray = Ray::new(self.center + right, direction - right / self.convergence());
+        Some((ray, 1.0))
+    }
+
+    fn convergence(&self) -> f64 {
+        self.stereo
+            .map_or(f64::INFINITY, |stereo| stereo.convergence)
+    }
+
+    // Traces from a point on the film through a point on the rear element, weighted by cos^4
+    // of the angle to the axis. Rays blocked inside the lens give the vignetting
+    fn realistic(&self, x: f64, y: f64, film_diagonal: f64) -> Option<(Ray, f64)> {
+        let lens = self.lens.as_ref().unwrap();
+        let (width, height) = self.image_size;
+
+        // The image is inverted on the film
+        let scale = film_diagonal / (width * width + height * height).sqrt();
+        let film_point = Point3::new((width / 2.0 - x) * scale, (y - height / 2.0) * scale, 0.0);
+
+        let p = Vec3::rand_in_unit_disk() * lens.rear_radius();
+        let rear_point = Point3::new(p.x(), p.y(), lens.rear_z());
+        let direction = (rear_point - film_point).unit();
+
+        let (origin, out_direction) = lens.trace_from_film((film_point, direction))?;
+
+        let cos2_theta = direction.z() * direction.z();
+        let to_world = |v: Vec3| v.x() * self.u + v.y() * self.v - v.z() * self.w;
+        let ray = Ray::new(self.center + to_world(origin), to_world(out_direction));
+        Some((ray, cos2_theta * cos2_theta))
+    }
+
+    fn fisheye(&self, x: f64, y: f64, fov: f64) -> Option<Vec3> {
+        let (width, height) = self.image_size;
+        let radius = width.min(height) / 2.0;
+
+        // Offset from the image center, up is positive
+        let (dx, dy) = ((x - width / 2.0) / radius, (height / 2.0 - y) / radius);
+        let r = (dx * dx + dy * dy).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = r * utils::deg_to_rad(fov) / 2.0;
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let (cos_phi, sin_phi) = if r > 0.0 {
+            (dx / r, dy / r)
+        } else {
+            (1.0, 0.0)
+        };
+
+        Some(sin_theta * (cos_phi * self.u + sin_phi * self.v) - cos_theta * self.w)
+    }
+
+    fn equirectangular(&self, x: f64, y: f64) -> Vec3 {
+        let (width, height) = self.image_size;
+
+        // Longitude 0 looks forward, latitude 0 is the horizon
+        let phi = (x / width - 0.5) * 2.0 * PI;
+        let theta = (0.5 - y / height) * PI;
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let (sin_theta, cos_theta) = theta.sin_cos();
+
+        cos_theta * sin_phi * self.u + sin_theta * self.v - cos_theta * cos_phi * self.w
+    }
+
+    fn cubemap(&self, x: f64, y: f64) -> Vec3 {
+        let (width, height) = self.image_size;
+        let (face_width, face_height) = (width / 3.0, height / 2.0);
+
+        let column = ((x / face_width) as usize).min(2);
+        let row = ((y / face_height) as usize).min(1);
+
+        // [-1, 1] across the face, up is positive
+        let a = 2.0 * (x / face_width - column as f64) - 1.0;
+        let b = 1.0 - 2.0 * (y / face_height - row as f64);
+
+        let (u, v, w) = (self.u, self.v, self.w);
+        // Forward, right and up of each face
+        let (forward, right, up) = match (row, column) {
+            (0, 0) => (u, w, v),
+            (0, 1) => (-u, -w, v),
+            (0, _) => (v, u, w),
+            (_, 0) => (-v, u, -w),
+            (_, 1) => (-w, u, v),
+            (_, _) => (w, -u, v),
+        };
+
+        forward + a * right + b * up
+    }
+
+    // None when the lens barrel blocks the aperture sample
+    fn defocus_disk_sample(&self, x: f64, y: f64) -> Option<Point3> {
+        let p = self.aperture.sample();
+
+        // Cat's eye, the aperture is clipped by a disk moving outwards with the film position
+        if self.cat_eye > 0.0 {
+            let (width, height) = self.image_size;
+            let half_diagonal = 0.5 * (width * width + height * height).sqrt();
+            let shift =
+                self.cat_eye * Vec3::new(x - width / 2.0, height / 2.0 - y, 0.0) / half_diagonal;
+            if (p - shift).len_sq() > 1.0 {
+                return None;
+            }
+        }
+
+        Some(self.center + (p.x() * self.defocus_disk_u) + (p.y() * self.defocus_disk_v))
+    }
+
+    // Anti-Aliasing, a film position inside pixel (i, j)
+    pub fn sample_pixel(i: u32, j: u32) -> (f64, f64) {
+        let (x, y) = sampler::get_2d();
+        (i as f64 + x, j as f64 + y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Looking down -z with y up, so u, v and w are the world axes
+    fn config(projection: Projection, resolution: (u32, u32)) -> RenderConfig {
+        RenderConfig {
+            resolution,
+            camera: CameraSettings {
+                lookfrom: Point3::new(0.0, 0.0, 0.0),
+                lookat: Point3::new(0.0, 0.0, -1.0),
+                vup: Vec3::new(0.0, 1.0, 0.0),
+                focus_dist: 1.0,
+                defocus_angle: 0.0,
+            },
+            projection,
+            ..Default::default()
+        }
+    }
+
+    fn camera(projection: Projection, resolution: (u32, u32)) -> Camera {
+        Camera::new(&config(projection, resolution)).unwrap()
+    }
+
+    fn assert_direction(camera: &Camera, (x, y): (f64, f64), expected: Vec3) {
+        let (ray, _) = camera.get_ray(x, y).unwrap();
+        let error = (ray.direction().unit() - expected).len();
+        assert!(error < 1e-9, "({x}, {y}) is off by {error}");
+    }
+
+    #[test]
+    fn fisheye_directions() {
+        let camera = camera(Projection::Fisheye { fov: 180.0 }, (100, 100));
+        assert_direction(&camera, (50.0, 50.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_direction(&camera, (100.0, 50.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_direction(&camera, (50.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(camera.get_ray(0.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn equirectangular_directions() {
+        let camera = camera(Projection::Equirectangular, (200, 100));
+        assert_direction(&camera, (100.0, 50.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_direction(&camera, (150.0, 50.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_direction(&camera, (0.0, 50.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_direction(&camera, (100.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn cubemap_face_centers() {
+        let camera = camera(Projection::Cubemap, (300, 200));
+        assert_direction(&camera, (50.0, 50.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_direction(&camera, (150.0, 50.0), Vec3::new(-1.0, 0.0, 0.0));
+        assert_direction(&camera, (250.0, 50.0), Vec3::new(0.0, 1.0, 0.0));
+        assert_direction(&camera, (50.0, 150.0), Vec3::new(0.0, -1.0, 0.0));
+        assert_direction(&camera, (150.0, 150.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_direction(&camera, (250.0, 150.0), Vec3::new(0.0, 0.0, 1.0));
+
+        // The front face's top edge meets the up face
+        assert_direction(&camera, (150.0, 100.0), Vec3::new(0.0, 1.0, -1.0).unit());
+    }
+
+    // Each half of the image is one eye's full view, offset by half the ipd to either side
+    #[test]
+    fn stereo_eye_split() {
+        for (layout, resolution, right_eye_pixel) in [
+            (StereoLayout::SideBySide, (200, 100), (150.0, 50.0)),
+            (StereoLayout::TopBottom, (100, 200), (50.0, 150.0)),
+        ] {
+            let stereo = Stereo {
+                ipd: 0.1,
+                layout,
+                ..Default::default()
+            };
+            let camera = Camera::new(&RenderConfig {
+                stereo: Some(stereo),
+                ..config(Projection::Perspective { vfov: 90.0 }, resolution)
+            })
+            .unwrap();
+
+            for (pixel, offset) in [((50.0, 50.0), -0.05), (right_eye_pixel, 0.05)] {
+                let (ray, _) = camera.get_ray(pixel.0, pixel.1).unwrap();
+                let error = (ray.origin() - Point3::new(offset, 0.0, 0.0)).len();
+                assert!(
+                    error < 1e-9,
+                    "{layout:?} eye at {pixel:?} is off by {error}"
+                );
+            }
+            assert_direction(&camera, right_eye_pixel, Vec3::new(0.0, 0.0, -1.0));
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::aov::Aov;
+use crate::aperture::Aperture;
+use crate::camera::{Projection, StereoLayout};
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::integrator::IntegratorKind;
+use crate::raytracer::{Crop, RenderConfig};
+use crate::sampler::{self, SamplerKind};
+
+const MAGIC: &[u8; 8] = b"RAYRSCK3";
+
+const AOVS: [Aov; 8] = [
+    Aov::Albedo,
+    Aov::Normal,
+    Aov::Depth,
+    Aov::Position,
+    Aov::ObjectId,
+    Aov::MaterialId,
+    Aov::Direct,
+    Aov::Indirect,
+];
+
+// Everything besides the film and the scene that decides what a resumed render adds to the
+// film. Samplers are deterministic in (pixel, sample index, seed), so this includes the
+// whole RNG state. Settings that only change when the render stops or how the film is
+// written out aren't checked: the time limit, pass size, checkpoint interval, intermediate
+// output, exposure, tone mapping, denoising and the heatmap. Adaptive sampling is ignored
+// by progressive renders
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckpointHeader {
+    pub seed: u64,
+    pub samples_per_pixel: u32,
+    pub samples_taken: u32,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    pub max_depth: u32,
+    pub integrator: IntegratorKind,
+    pub crop: Option<Crop>,
+    // See `view_fingerprint`
+    pub view: u64,
+    pub noise_threshold: Option<f64>,
+    // The film's layers, denoiser guides included
+    pub aovs: Vec<Aov>,
+}
+
+// Hash of everything that decides the camera rays: placement, aspect ratio, projection,
+// stereo, aperture and cat's eye. Lens systems and aperture masks are hashed whole
+pub fn view_fingerprint(config: &RenderConfig) -> u64 {
+    let camera = &config.camera;
+    let mut values = Vec::new();
+    for v in [camera.lookfrom, camera.lookat, camera.vup] {
+        values.extend(v.xyz());
+    }
+    values.extend([camera.focus_dist, camera.defocus_angle, config.aspect_ratio]);
+
+    match &config.projection {
+        Projection::Perspective { vfov } => values.extend([0.0, *vfov]),
+        Projection::Orthographic { height } => values.extend([1.0, *height]),
+        Projection::Fisheye { fov } => values.extend([2.0, *fov]),
+        Projection::Equirectangular => values.push(3.0),
+        Projection::Cubemap => values.push(4.0),
+        Projection::Realistic {
+            lens,
+            film_diagonal,
+        } => {
+            values.extend([5.0, *film_diagonal]);
+            for e in lens.elements() {
+                values.extend([e.curvature_radius, e.thickness, e.eta, e.aperture_radius]);
+            }
+        }
+    }
+
+    match &config.stereo {
+        Some(stereo) => {
+            let layout = match stereo.layout {
+                StereoLayout::SideBySide => 0.0,
+                StereoLayout::TopBottom => 1.0,
+            };
+            values.extend([1.0, stereo.ipd, stereo.convergence, layout]);
+        }
+        None => values.push(0.0),
+    }
+
+    match &config.aperture {
+        Aperture::Disk => values.push(0.0),
+        Aperture::Polygon { blades, rotation } => {
+            values.extend([1.0, *blades as f64, *rotation]);
+        }
+        Aperture::Mask(mask) => {
+            values.extend([2.0, mask.width() as f64, mask.height() as f64]);
+            values.extend(mask.cdf());
+        }
+    }
+    values.push(config.cat_eye);
+
+    let bits: Vec<u64> = values.iter().map(|v| v.to_bits()).collect();
+    sampler::hash(&bits)
+}
+
+// Written to a temporary file first so a crash while saving keeps the previous checkpoint
+pub fn save(path: impl AsRef<Path>, header: &CheckpointHeader, film: &Film) -> io::Result<()> {
+    let path = path.as_ref();
+    let tmp_path = path.with_extension("tmp");
+
+    let mut out = BufWriter::new(File::create(&tmp_path)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&film.width().to_le_bytes())?;
+    out.write_all(&film.height().to_le_bytes())?;
+    out.write_all(&(film.layer_count() as u32).to_le_bytes())?;
+    write_header(&mut out, header)?;
+    film.write_state(&mut out)?;
+    out.into_inner()?.sync_all()?;
+
+    fs::rename(tmp_path, path)
+}
+
+// Restores the film's accumulation state, which has to match the checkpoint's resolution
+// and number of layers
+pub fn load(path: impl AsRef<Path>, film: &mut Film) -> io::Result<CheckpointHeader> {
+    let mut input = BufReader::new(File::open(path)?);
+
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a checkpoint"));
+    }
+
+    let shape = [
+        read_u32(&mut input)?,
+        read_u32(&mut input)?,
+        read_u32(&mut input)?,
+    ];
+    if shape != [film.width(), film.height(), film.layer_count() as u32] {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "checkpoint resolution or AOVs don't match",
+        ));
+    }
+
+    let header = read_header(&mut input)?;
+    film.read_state(&mut input)?;
+
+    Ok(header)
+}
+
+// Enums are stored as a tag followed by their parameters
+fn write_header(out: &mut impl Write, header: &CheckpointHeader) -> io::Result<()> {
+    let sampler = match header.sampler {
+        SamplerKind::Independent => 0,
+        SamplerKind::Stratified => 1,
+        SamplerKind::Halton => 2,
+        SamplerKind::Sobol => 3,
+    };
+    let (filter, filter_params) = match header.filter {
+        Filter::Box { radius } => (0, [radius, 0.0, 0.0]),
+        Filter::Tent { radius } => (1, [radius, 0.0, 0.0]),
+        Filter::Gaussian { radius, sigma } => (2, [radius, sigma, 0.0]),
+        Filter::Mitchell { radius, b, c } => (3, [radius, b, c]),
+        Filter::Lanczos { radius, tau } => (4, [radius, tau, 0.0]),
+    };
+    let (integrator, integrator_param): (u32, f64) = match header.integrator {
+        IntegratorKind::PathTracer => (0, 0.0),
+        IntegratorKind::Normals => (1, 0.0),
+        IntegratorKind::Uv => (2, 0.0),
+        IntegratorKind::Depth { far } => (3, far),
+        IntegratorKind::Barycentrics => (4, 0.0),
+        IntegratorKind::MaterialId => (5, 0.0),
+        IntegratorKind::Albedo => (6, 0.0),
+        IntegratorKind::AmbientOcclusion { distance } => (7, distance),
+    };
+    let crop = match &header.crop {
+        Some(crop) => [
+            1,
+            crop.x.start,
+            crop.x.end,
+            crop.y.start,
+            crop.y.end,
+            crop.full_size as u32,
+        ],
+        None => [0; 6],
+    };
+
+    out.write_all(&header.seed.to_le_bytes())?;
+    for value in [
+        header.samples_per_pixel,
+        header.samples_taken,
+        sampler,
+        filter,
+    ] {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    for value in filter_params {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    out.write_all(&header.max_depth.to_le_bytes())?;
+    out.write_all(&integrator.to_le_bytes())?;
+    out.write_all(&integrator_param.to_le_bytes())?;
+    for value in crop {
+        out.write_all(&value.to_le_bytes())?;
+    }
+
+    out.write_all(&header.view.to_le_bytes())?;
+    let (has_threshold, threshold) = match header.noise_threshold {
+        Some(threshold) => (1u32, threshold),
+        None => (0, 0.0),
+    };
+    out.write_all(&has_threshold.to_le_bytes())?;
+    out.write_all(&threshold.to_le_bytes())?;
+    out.write_all(&(header.aovs.len() as u32).to_le_bytes())?;
+    for aov in &header.aovs {
+        let tag = AOVS.iter().position(|a| a == aov).unwrap() as u32;
+        out.write_all(&tag.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_header(input: &mut impl Read) -> io::Result<CheckpointHeader> {
+    let invalid = |what| Error::new(ErrorKind::InvalidData, format!("unknown {what}"));
+
+    let seed = read_u64(input)?;
+    let samples_per_pixel = read_u32(input)?;
+    let samples_taken = read_u32(input)?;
+
+    let sampler = match read_u32(input)? {
+        0 => SamplerKind::Independent,
+        1 => SamplerKind::Stratified,
+        2 => SamplerKind::Halton,
+        3 => SamplerKind::Sobol,
+        _ => return Err(invalid("sampler")),
+    };
+
+    let filter = read_u32(input)?;
+    let [radius, p1, p2] = [read_f64(input)?, read_f64(input)?, read_f64(input)?];
+    let filter = match filter {
+        0 => Filter::Box { radius },
+        1 => Filter::Tent { radius },
+        2 => Filter::Gaussian { radius, sigma: p1 },
+        3 => Filter::Mitchell {
+            radius,
+            b: p1,
+            c: p2,
+        },
+        4 => Filter::Lanczos { radius, tau: p1 },
+        _ => return Err(invalid("filter")),
+    };
+
+    let max_depth = read_u32(input)?;
+    let integrator = read_u32(input)?;
+    let param = read_f64(input)?;
+    let integrator = match integrator {
+        0 => IntegratorKind::PathTracer,
+        1 => IntegratorKind::Normals,
+        2 => IntegratorKind::Uv,
+        3 => IntegratorKind::Depth { far: param },
+        4 => IntegratorKind::Barycentrics,
+        5 => IntegratorKind::MaterialId,
+        6 => IntegratorKind::Albedo,
+        7 => IntegratorKind::AmbientOcclusion { distance: param },
+        _ => return Err(invalid("integrator")),
+    };
+
+    let mut crop = [0; 6];
+    for value in &mut crop {
+        *value = read_u32(input)?;
+    }
+    let crop = (crop[0] != 0).then(|| Crop {
+        x: crop[1]..crop[2],
+        y: crop[3]..crop[4],
+        full_size: crop[5] != 0,
+    });
+
+    let view = read_u64(input)?;
+    let has_threshold = read_u32(input)? != 0;
+    let threshold = read_f64(input)?;
+    let noise_threshold = has_threshold.then_some(threshold);
+    let aov_count = read_u32(input)?;
+    let aovs = (0..aov_count)
+        .map(|_| {
+            let tag = read_u32(input)?;
+            AOVS.get(tag as usize)
+                .copied()
+                .ok_or_else(|| invalid("aov"))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    Ok(CheckpointHeader {
+        seed,
+        samples_per_pixel,
+        samples_taken,
+        sampler,
+        filter,
+        max_depth,
+        integrator,
+        crop,
+        view,
+        noise_threshold,
+        aovs,
+    })
+}
+
+pub(crate) fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_f64(input: &mut impl Read) -> io::Result<f64> {
+    read_u64(input).map(f64::from_bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Filter;
+    use crate::utils::Color;
+
+    #[test]
+    fn round_trip() {
+        let filter = Filter::Gaussian {
+            radius: 1.5,
+            sigma: 0.5,
+        };
+        let mut film = Film::new(4, 3, filter);
+        for (i, j) in [(0, 0), (2, 1), (3, 2)] {
+            let mut tile = film.tile_for_pixel(i, j);
+            tile.add_sample(
+                (i as f64 + 0.3, j as f64 + 0.7),
+                Color::new(0.1, 0.5, 2.0),
+                &[],
+            );
+            tile.add_sample(
+                (i as f64 + 0.6, j as f64 + 0.2),
+                Color::new(1.0, 0.3, 0.0),
+                &[],
+            );
+            film.merge_tile(&tile);
+        }
+
+        let header = CheckpointHeader {
+            seed: 7,
+            samples_per_pixel: 64,
+            samples_taken: 2,
+            sampler: SamplerKind::Sobol,
+            filter,
+            max_depth: 12,
+            integrator: IntegratorKind::AmbientOcclusion { distance: 0.5 },
+            crop: Some(Crop {
+                x: 1..3,
+                y: 0..2,
+                full_size: true,
+            }),
+            view: 0x1234_5678_9abc_def0,
+            noise_threshold: Some(0.02),
+            aovs: vec![Aov::Normal, Aov::Indirect],
+        };
+        let path = std::env::temp_dir().join(format!("rayrs-{}.checkpoint", std::process::id()));
+        save(&path, &header, &film).unwrap();
+
+        let mut restored = Film::new(4, 3, filter);
+        let loaded = load(&path, &mut restored);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), header);
+        for j in 0..3 {
+            for i in 0..4 {
+                let (a, b) = (restored.pixel_color(i, j), film.pixel_color(i, j));
+                assert_eq!((a.x(), a.y(), a.z()), (b.x(), b.y(), b.z()));
+                assert_eq!(restored.stats(i, j).variance(), film.stats(i, j).variance());
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use rayon::prelude::*;
+
+use crate::image::Image;
+use crate::utils::{self, Color};
+
+// Edge avoiding à-trous wavelet filter (Dammertz et al. 2010) with the variance guided
+// luminance weights of SVGF (Schied et al. 2017)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Denoiser {
+    // Each iteration doubles the filter footprint, 5 covers 61 x 61 pixels
+    pub iterations: u32,
+    // In standard deviations of the pixel's luminance
+    pub sigma_luminance: f64,
+    // Exponent on the cosine between normals
+    pub sigma_normal: f64,
+    // Relative depth difference
+    pub sigma_depth: f64,
+    pub sigma_albedo: f64,
+    // Filters lighting divided by albedo and multiplies it back, keeping texture detail.
+    // Only pays off with a clean albedo, stochastic layered materials make it speckle
+    pub demodulate: bool,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_luminance: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 0.1,
+            sigma_albedo: 1.0,
+            demodulate: false,
+        }
+    }
+}
+
+// Missing guides don't stop the filter, without variance every luminance change does
+pub struct DenoiseInput<'a> {
+    pub color: &'a Image,
+    pub albedo: Option<&'a Image>,
+    pub normal: Option<&'a Image>,
+    pub depth: Option<&'a Image>,
+    // Variance of each pixel's mean luminance
+    pub variance: Option<&'a [f64]>,
+}
+
+// B3 spline, by distance from the center tap
+const KERNEL: [f64; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+const ALBEDO_EPSILON: f64 = 1e-3;
+
+impl Denoiser {
+    pub fn denoise(&self, input: &DenoiseInput) -> Image {
+        let (width, height) = (input.color.width(), input.color.height());
+        let buffer = |image: Option<&Image>| {
+            image.map(|image| {
+                (0..height)
+                    .flat_map(|y| (0..width).map(move |x| image.get(x, y)))
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        let color = buffer(Some(input.color)).unwrap();
+        let guides = Guides {
+            width,
+            height,
+            normal: buffer(input.normal),
+            depth: buffer(input.depth),
+            albedo: buffer(input.albedo),
+        };
+        let albedo = guides.albedo.as_ref().filter(|_| self.demodulate);
+
+        let demodulate = |idx: usize| match albedo {
+            Some(albedo) => {
+                let [r, g, b] = albedo[idx].xyz().map(|a| a.max(ALBEDO_EPSILON));
+                color[idx] / Color::new(r, g, b)
+            }
+            None => color[idx],
+        };
+        let mut irradiance: Vec<Color> = (0..color.len()).map(demodulate).collect();
+
+        // Demodulation scales the luminance, and its standard deviation with it
+        let mut variance: Vec<f64> = match input.variance {
+            Some(variance) => (0..color.len())
+                .map(|idx| {
+                    let l = utils::luminance(color[idx]);
+                    if l <= 0.0 {
+                        return variance[idx];
+                    }
+                    let scale = utils::luminance(irradiance[idx]) / l;
+                    variance[idx] * scale * scale
+                })
+                .collect(),
+            None => vec![0.0; color.len()],
+        };
+
+        for iteration in 0..self.iterations {
+            (irradiance, variance) =
+                self.filter_pass(&guides, &irradiance, &variance, 1 << iteration);
+        }
+
+        let mut output = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let idx = (y * width + x) as usize;
+                let filtered = match albedo {
+                    Some(albedo) => {
+                        let [r, g, b] = albedo[idx].xyz().map(|a| a.max(ALBEDO_EPSILON));
+                        irradiance[idx] * Color::new(r, g, b)
+                    }
+                    None => irradiance[idx],
+                };
+                output.set(x, y, filtered);
+            }
+        }
+        output
+    }
+
+    fn filter_pass(
+        &self,
+        guides: &Guides,
+        color: &[Color],
+        variance: &[f64],
+        step: i64,
+    ) -> (Vec<Color>, Vec<f64>) {
+        let (width, height) = (guides.width as i64, guides.height as i64);
+        let blurred_variance = blur_3x3(variance, width, height);
+
+        (0..color.len())
+            .into_par_iter()
+            .map(|p| {
+                let (px, py) = (p as i64 % width, p as i64 / width);
+                let l_p = utils::luminance(color[p]);
+                let luminance_scale = self.sigma_luminance * blurred_variance[p].sqrt() + 1e-10;
+
+                let mut sum = Color::default();
+                let mut weight_sum = 0.0;
+                let mut variance_sum = 0.0;
+
+                for dy in -2..=2i64 {
+                    for dx in -2..=2i64 {
+                        let (qx, qy) = (px + dx * step, py + dy * step);
+                        if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                            continue;
+                        }
+                        let q = (qy * width + qx) as usize;
+
+                        let kernel =
+                            KERNEL[dx.unsigned_abs() as usize] * KERNEL[dy.unsigned_abs() as usize];
+                        let weight = if p == q {
+                            kernel
+                        } else {
+                            let l_q = utils::luminance(color[q]);
+                            let w_luminance = (-(l_p - l_q).abs() / luminance_scale).exp();
+                            kernel * w_luminance * self.guide_weight(guides, p, q)
+                        };
+
+                        sum += weight * color[q];
+                        weight_sum += weight;
+                        variance_sum += weight * weight * variance[q];
+                    }
+                }
+
+                (sum / weight_sum, variance_sum / (weight_sum * weight_sum))
+            })
+            .unzip()
+    }
+
+    fn guide_weight(&self, guides: &Guides, p: usize, q: usize) -> f64 {
+        let mut weight = 1.0;
+
+        if let Some(normal) = &guides.normal {
+            weight *= normal[p].dot(&normal[q]).max(0.0).powf(self.sigma_normal);
+        }
+        if let Some(depth) = &guides.depth {
+            let (z_p, z_q) = (depth[p].x(), depth[q].x());
+            weight *= (-(z_p - z_q).abs() / (self.sigma_depth * z_p.max(z_q) + 1e-10)).exp();
+        }
+        if let Some(albedo) = &guides.albedo {
+            let diff = (albedo[p] - albedo[q]).len_sq();
+            weight *= (-diff / (self.sigma_albedo * self.sigma_albedo)).exp();
+        }
+
+        weight
+    }
+}
+
+struct Guides {
+    width: u32,
+    height: u32,
+    normal: Option<Vec<Color>>,
+    depth: Option<Vec<Color>>,
+    albedo: Option<Vec<Color>>,
+}
+
+// Smooths the variance estimate before it's used for the luminance weights
+fn blur_3x3(values: &[f64], width: i64, height: i64) -> Vec<f64> {
+    const GAUSSIAN: [f64; 2] = [1.0 / 2.0, 1.0 / 4.0];
+
+    (0..values.len())
+        .map(|p| {
+            let (px, py) = (p as i64 % width, p as i64 / width);
+            let mut sum = 0.0;
+            let mut weight_sum = 0.0;
+
+            for dy in -1..=1i64 {
+                for dx in -1..=1i64 {
+                    let (qx, qy) = (px + dx, py + dy);
+                    if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                        continue;
+                    }
+                    let weight =
+                        GAUSSIAN[dx.unsigned_abs() as usize] * GAUSSIAN[dy.unsigned_abs() as usize];
+                    sum += weight * values[(qy * width + qx) as usize];
+                    weight_sum += weight;
+                }
+            }
+
+            sum / weight_sum
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{self, IndependentSampler};
+
+    // Noise on two flat regions split by a normal edge is smoothed without blurring the edge
+    #[test]
+    fn smooths_noise_keeps_edges() {
+        let (width, height) = (32, 32);
+        let mut color = Image::new(width, height);
+        let mut normal = Image::new(width, height);
+        let noise_variance = 0.1 * 0.1 / 3.0;
+
+        sampler::install(Box::new(IndependentSampler::new(7)));
+        for y in 0..height {
+            for x in 0..width {
+                let (base, n) = if x < width / 2 {
+                    (0.2, Color::new(0.0, 1.0, 0.0))
+                } else {
+                    (0.8, Color::new(1.0, 0.0, 0.0))
+                };
+                let value = base + 0.1 * (2.0 * sampler::get_1d() - 1.0);
+                color.set(x, y, Color::new(value, value, value));
+                normal.set(x, y, n);
+            }
+        }
+        sampler::uninstall();
+
+        let variance = vec![noise_variance; (width * height) as usize];
+        let output = Denoiser::default().denoise(&DenoiseInput {
+            color: &color,
+            albedo: None,
+            normal: Some(&normal),
+            depth: None,
+            variance: Some(&variance),
+        });
+
+        let mut squared_error = 0.0;
+        for y in 0..height {
+            for x in 0..width {
+                let expected = if x < width / 2 { 0.2 } else { 0.8 };
+                squared_error += (output.get(x, y).x() - expected).powi(2);
+            }
+        }
+
+        let mse = squared_error / (width * height) as f64;
+        assert!(mse < 0.1 * noise_variance, "mse {mse}");
+    }
+}
@@ -0,0 +1,302 @@
+use std::io::{self, Read, Write};
+
+use crate::checkpoint::{read_f64, read_u32};
+use crate::filter::Filter;
+use crate::image::Image;
+use crate::utils::{self, Color};
+
+// Welford's running mean and variance of a pixel's sample luminance
+#[derive(Copy, Clone, Default)]
+pub struct VarianceEstimator {
+    count: u32,
+    mean: f64,
+    m2: f64,
+}
+
+impl VarianceEstimator {
+    pub fn add(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    // Chan et al. parallel update
+    pub fn merge(&mut self, other: &VarianceEstimator) {
+        if other.count == 0 {
+            return;
+        }
+
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.m2 += other.m2 + delta * delta * self.count as f64 * other.count as f64 / count as f64;
+        self.count = count;
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        self.m2 / (self.count - 1) as f64
+    }
+
+    // Standard error of the mean relative to the mean, offset so black pixels can converge
+    pub fn relative_error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        (self.variance() / self.count as f64).sqrt() / (self.mean.abs() + 1e-2)
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct Pixel {
+    pub sum: Color,
+    pub weight_sum: f64,
+}
+
+impl Pixel {
+    fn merge(&mut self, other: &Pixel) {
+        self.sum += other.sum;
+        self.weight_sum += other.weight_sum;
+    }
+
+    // Weighted average of the samples splatted into the pixel
+    fn color(&self) -> Color {
+        // Negative lobes can cancel out the weights of sparsely sampled pixels
+        if self.weight_sum.abs() < 1e-12 {
+            return Color::default();
+        }
+        self.sum / self.weight_sum
+    }
+}
+
+// Accumulates filtered samples for the whole image, and for each AOV layer
+pub struct Film {
+    width: u32,
+    height: u32,
+    filter: Filter,
+    pixels: Vec<Pixel>,
+    stats: Vec<VarianceEstimator>,
+    layers: Vec<Vec<Pixel>>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Self {
+        Self {
+            width,
+            height,
+            filter,
+            pixels: vec![Pixel::default(); (width * height) as usize],
+            stats: vec![VarianceEstimator::default(); (width * height) as usize],
+            layers: Vec::new(),
+        }
+    }
+
+    // Adds layers for auxiliary values splatted along with every sample
+    pub fn with_layers(mut self, count: usize) -> Self {
+        self.layers = vec![self.pixels.clone(); count];
+        self
+    }
+
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // Tile covering every pixel a sample taken inside pixel (i, j) can reach
+    pub fn tile_for_pixel(&self, i: u32, j: u32) -> FilmTile {
+        let reach = (self.filter.radius() + 0.5).ceil() as u32;
+
+        let x0 = i.saturating_sub(reach);
+        let y0 = j.saturating_sub(reach);
+        let x1 = (i + reach + 1).min(self.width);
+        let y1 = (j + reach + 1).min(self.height);
+        let pixels = vec![Pixel::default(); ((x1 - x0) * (y1 - y0)) as usize];
+
+        FilmTile {
+            center: (i, j),
+            stats: VarianceEstimator::default(),
+            x0,
+            y0,
+            width: x1 - x0,
+            height: y1 - y0,
+            filter: self.filter,
+            layers: vec![pixels.clone(); self.layers.len()],
+            pixels,
+        }
+    }
+
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        for ty in 0..tile.height {
+            for tx in 0..tile.width {
+                let pixel = &tile.pixels[(ty * tile.width + tx) as usize];
+                let idx = ((tile.y0 + ty) * self.width + tile.x0 + tx) as usize;
+                self.pixels[idx].merge(pixel);
+
+                for (layer, tile_layer) in self.layers.iter_mut().zip(&tile.layers) {
+                    layer[idx].merge(&tile_layer[(ty * tile.width + tx) as usize]);
+                }
+            }
+        }
+
+        let (i, j) = tile.center;
+        self.stats[(j * self.width + i) as usize].merge(&tile.stats);
+    }
+
+    // Statistics of the samples taken inside the pixel, independent of the filter
+    pub fn stats(&self, x: u32, y: u32) -> &VarianceEstimator {
+        &self.stats[(y * self.width + x) as usize]
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> &Pixel {
+        &self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn pixel_color(&self, x: u32, y: u32) -> Color {
+        self.pixel(x, y).color()
+    }
+
+    pub fn layer_color(&self, layer: usize, x: u32, y: u32) -> Color {
+        self.layers[layer][(y * self.width + x) as usize].color()
+    }
+
+    pub fn image(&self) -> Image {
+        self.to_image(&self.pixels)
+    }
+
+    pub fn layer_image(&self, layer: usize) -> Image {
+        self.to_image(&self.layers[layer])
+    }
+
+    fn to_image(&self, pixels: &[Pixel]) -> Image {
+        let mut image = Image::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                image.set(x, y, pixels[(y * self.width + x) as usize].color());
+            }
+        }
+        image
+    }
+
+    // Raw accumulation state, bit exact so a resumed render matches an uninterrupted one
+    pub fn write_state(&self, out: &mut impl Write) -> io::Result<()> {
+        for (pixel, stats) in self.pixels.iter().zip(&self.stats) {
+            let sum = pixel.sum;
+            for value in [
+                sum.x(),
+                sum.y(),
+                sum.z(),
+                pixel.weight_sum,
+                stats.mean,
+                stats.m2,
+            ] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            out.write_all(&stats.count.to_le_bytes())?;
+        }
+
+        for pixel in self.layers.iter().flatten() {
+            let sum = pixel.sum;
+            for value in [sum.x(), sum.y(), sum.z(), pixel.weight_sum] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_state(&mut self, input: &mut impl Read) -> io::Result<()> {
+        for (pixel, stats) in self.pixels.iter_mut().zip(&mut self.stats) {
+            let sum = Color::new(read_f64(input)?, read_f64(input)?, read_f64(input)?);
+            *pixel = Pixel {
+                sum,
+                weight_sum: read_f64(input)?,
+            };
+            stats.mean = read_f64(input)?;
+            stats.m2 = read_f64(input)?;
+            stats.count = read_u32(input)?;
+        }
+
+        for pixel in self.layers.iter_mut().flatten() {
+            let sum = Color::new(read_f64(input)?, read_f64(input)?, read_f64(input)?);
+            *pixel = Pixel {
+                sum,
+                weight_sum: read_f64(input)?,
+            };
+        }
+        Ok(())
+    }
+}
+
+// Samples of a single pixel, splatted into its neighbourhood
+pub struct FilmTile {
+    center: (u32, u32),
+    stats: VarianceEstimator,
+    x0: u32,
+    y0: u32,
+    width: u32,
+    height: u32,
+    filter: Filter,
+    pixels: Vec<Pixel>,
+    layers: Vec<Vec<Pixel>>,
+}
+
+impl FilmTile {
+    pub fn stats(&self) -> &VarianceEstimator {
+        &self.stats
+    }
+
+    // The pixel the samples are taken in
+    pub fn center(&self) -> (u32, u32) {
+        self.center
+    }
+
+    // Splats a sample at continuous film position (x, y) into every pixel under the filter,
+    // with one value per layer
+    pub fn add_sample(&mut self, (x, y): (f64, f64), color: Color, layers: &[Color]) {
+        self.stats.add(utils::luminance(color));
+
+        let radius = self.filter.radius();
+
+        let x_min = ((x - 0.5 - radius).ceil() as i64).max(self.x0 as i64);
+        let y_min = ((y - 0.5 - radius).ceil() as i64).max(self.y0 as i64);
+        let x_max = ((x - 0.5 + radius).floor() as i64).min((self.x0 + self.width) as i64 - 1);
+        let y_max = ((y - 0.5 + radius).floor() as i64).min((self.y0 + self.height) as i64 - 1);
+
+        for py in y_min..=y_max {
+            for px in x_min..=x_max {
+                let weight = self
+                    .filter
+                    .evaluate(x - (px as f64 + 0.5), y - (py as f64 + 0.5));
+                if weight == 0.0 {
+                    continue;
+                }
+
+                let idx = ((py as u32 - self.y0) * self.width + px as u32 - self.x0) as usize;
+                self.pixels[idx].sum += weight * color;
+                self.pixels[idx].weight_sum += weight;
+
+                for (layer, &value) in self.layers.iter_mut().zip(layers) {
+                    layer[idx].sum += weight * value;
+                    layer[idx].weight_sum += weight;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::f64::consts::PI;
+
+// Pixel reconstruction filters, offsets and radii are in pixels
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    Box { radius: f64 },
+    Tent { radius: f64 },
+    Gaussian { radius: f64, sigma: f64 },
+    // Mitchell-Netravali, b = c = 1/3 is the recommended setting
+    Mitchell { radius: f64, b: f64, c: f64 },
+    // Windowed sinc, tau is the number of sinc lobes in the window
+    Lanczos { radius: f64, tau: f64 },
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+impl Filter {
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius, .. } => radius,
+        }
+    }
+
+    // All filters are separable
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        if x > self.radius() {
+            return 0.0;
+        }
+
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { radius } => radius - x,
+            Filter::Gaussian { radius, sigma } => {
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Filter::Mitchell { radius, b, c } => mitchell_1d(2.0 * x / radius, b, c),
+            Filter::Lanczos { tau, .. } => sinc(x) * sinc(x / tau),
+        }
+    }
+}
+
+// Defined on [-2, 2]
+fn mitchell_1d(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    let (x2, x3) = (x * x, x * x * x);
+
+    if x <= 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x3 + (-18.0 + 12.0 * b + 6.0 * c) * x2 + (6.0 - 2.0 * b))
+            / 6.0
+    } else if x <= 2.0 {
+        ((-b - 6.0 * c) * x3
+            + (6.0 * b + 30.0 * c) * x2
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        0.0
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::film::Film;
+    use crate::utils::Color;
+
+    // Splats n x n stratified samples of color(x, y) into every pixel of the film
+    fn render(filter: Filter, n: u32, color: impl Fn(f64, f64) -> Color) -> Film {
+        let mut film = Film::new(5, 5, filter);
+        for j in 0..5 {
+            for i in 0..5 {
+                let mut tile = film.tile_for_pixel(i, j);
+                for s in 0..n * n {
+                    let x = i as f64 + ((s % n) as f64 + 0.5) / n as f64;
+                    let y = j as f64 + ((s / n) as f64 + 0.5) / n as f64;
+                    tile.add_sample((x, y), color(x, y), &[]);
+                }
+                film.merge_tile(&tile);
+            }
+        }
+        film
+    }
+
+    #[test]
+    fn box_is_the_pixel_average() {
+        let color = |x: f64, y: f64| Color::new(x * x, y, x * y);
+        let film = render(Filter::Box { radius: 0.5 }, 4, color);
+
+        for j in 0..5 {
+            for i in 0..5 {
+                let mut average = Color::default();
+                for s in 0..16 {
+                    let x = i as f64 + ((s % 4) as f64 + 0.5) / 4.0;
+                    let y = j as f64 + ((s / 4) as f64 + 0.5) / 4.0;
+                    average += color(x, y) / 16.0;
+                }
+
+                let pixel = film.pixel_color(i, j);
+                for (a, b) in pixel.xyz().into_iter().zip(average.xyz()) {
+                    assert!((a - b).abs() < 1e-12, "({i}, {j}): {a} != {b}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn zero_outside_radius() {
+        let filters = [
+            Filter::Mitchell {
+                radius: 2.0,
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            },
+            Filter::Lanczos {
+                radius: 3.0,
+                tau: 3.0,
+            },
+        ];
+
+        for filter in filters {
+            let r = filter.radius();
+            assert!(filter.evaluate(0.0, 0.0) > 0.0);
+            for d in [r + 1e-6, r + 0.5, 2.0 * r] {
+                assert_eq!(filter.evaluate(d, 0.0), 0.0, "{filter:?}");
+                assert_eq!(filter.evaluate(0.0, -d), 0.0, "{filter:?}");
+                assert_eq!(filter.evaluate(d, d), 0.0, "{filter:?}");
+            }
+        }
+    }
+
+    // Every pixel divides by the weights it got, negative lobes included, so a flat image
+    // stays flat whatever the filter
+    #[test]
+    fn weighted_sum_is_normalized() {
+        let filters = [
+            Filter::Tent { radius: 1.5 },
+            Filter::Gaussian {
+                radius: 1.5,
+                sigma: 0.5,
+            },
+            Filter::Mitchell {
+                radius: 2.0,
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            },
+            Filter::Lanczos {
+                radius: 2.0,
+                tau: 2.0,
+            },
+        ];
+
+        let flat = Color::new(0.2, 0.5, 0.9);
+        for filter in filters {
+            let film = render(filter, 3, |_, _| flat);
+            for j in 0..5 {
+                for i in 0..5 {
+                    let pixel = film.pixel_color(i, j);
+                    for (a, b) in pixel.xyz().into_iter().zip(flat.xyz()) {
+                        assert!((a - b).abs() < 1e-9, "{filter:?} ({i}, {j}): {a} != {b}");
+                    }
+                }
+            }
+        }
+    }
+}
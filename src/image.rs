@@ -0,0 +1,153 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, ErrorKind, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::utils::Color;
+
+pub struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::default(); (width * height) as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: Color) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    pub fn crop(&self, x: Range<u32>, y: Range<u32>) -> Image {
+        let mut cropped = Image::new(x.len() as u32, y.len() as u32);
+        for (cy, sy) in y.enumerate() {
+            for (cx, sx) in x.clone().enumerate() {
+                cropped.set(cx as u32, cy as u32, self.get(sx, sy));
+            }
+        }
+        cropped
+    }
+
+    // Copies image over this one with its top left corner at (x, y)
+    pub fn paste(&mut self, image: &Image, (x, y): (u32, u32)) {
+        for j in 0..image.height {
+            for i in 0..image.width {
+                self.set(x + i, y + j, image.get(i, j));
+            }
+        }
+    }
+
+    // Color pfm, little endian floats with the rows stored bottom to top
+    pub fn write_pfm(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                for c in self.get(x, y).xyz() {
+                    out.write_all(&(c as f32).to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    }
+
+    // Binary ppm, values in [0, 1] are written without encoding
+    pub fn write_ppm(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for color in &self.pixels {
+            for c in color.xyz() {
+                out.write_all(&[(255.0 * c.clamp(0.0, 1.0)).round() as u8])?;
+            }
+        }
+        out.flush()
+    }
+
+    // Reads a binary (P6) or ascii (P3) ppm, values are scaled to [0, 1] without decoding
+    pub fn read_ppm(path: impl AsRef<Path>) -> io::Result<Image> {
+        let data = fs::read(path)?;
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+
+        let mut pos = 0;
+        let mut next_token = || -> io::Result<String> {
+            loop {
+                while pos < data.len() && data[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                if pos < data.len() && data[pos] == b'#' {
+                    while pos < data.len() && data[pos] != b'\n' {
+                        pos += 1;
+                    }
+                    continue;
+                }
+                break;
+            }
+
+            let start = pos;
+            while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid("unexpected end of ppm"));
+            }
+
+            Ok(String::from_utf8_lossy(&data[start..pos]).into_owned())
+        };
+
+        let parse = |token: String| token.parse::<u32>().map_err(|_| invalid("bad ppm header"));
+
+        let magic = next_token()?;
+        let width = parse(next_token()?)?;
+        let height = parse(next_token()?)?;
+        let max_val = parse(next_token()?)? as f64;
+
+        let mut image = Image::new(width, height);
+        let count = (width * height * 3) as usize;
+
+        let samples: Vec<f64> = match magic.as_str() {
+            "P3" => (0..count)
+                .map(|_| next_token().and_then(parse).map(|v| v as f64))
+                .collect::<io::Result<_>>()?,
+            "P6" => {
+                // Exactly one whitespace byte separates the header from the raster
+                let start = pos + 1;
+                let bytes_per_sample = if max_val > 255.0 { 2 } else { 1 };
+                let raster = data
+                    .get(start..start + count * bytes_per_sample)
+                    .ok_or_else(|| invalid("truncated ppm"))?;
+
+                raster
+                    .chunks(bytes_per_sample)
+                    .map(|c| c.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32) as f64)
+                    .collect()
+            }
+            _ => return Err(invalid("unsupported ppm format")),
+        };
+
+        for (pixel, rgb) in image.pixels.iter_mut().zip(samples.chunks(3)) {
+            *pixel = Color::new(rgb[0], rgb[1], rgb[2]) / max_val;
+        }
+
+        Ok(image)
+    }
+}
@@ -0,0 +1,379 @@
+use crate::aov::{self, FirstHit, PathSample};
+use crate::material::ScatterRecord;
+use crate::microfacet::Frame;
+use crate::ray::Ray;
+use crate::scene::{HitRecord, Hittable, Scene};
+use crate::stats;
+use crate::utils::Color;
+use crate::vec3::{Point3, Vec3};
+
+// Turns a camera ray into a sample, recording every bounce into path when given
+pub trait Integrator: Send + Sync {
+    fn trace(&self, scene: &Scene, ray: &Ray, path: Option<&mut Vec<PathEvent>>) -> PathSample;
+}
+
+// The path tracer renders the image, the others are diagnostic views of the first surface
+// hit, black where the ray escapes
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum IntegratorKind {
+    #[default]
+    PathTracer,
+    // Shading normal mapped from [-1, 1] to [0, 1]
+    Normals,
+    Uv,
+    // White at the camera, fading to black at far
+    Depth {
+        far: f64,
+    },
+    Barycentrics,
+    MaterialId,
+    // The surface's color without any lighting
+    Albedo,
+    // White where a cosine distributed ray from the surface escapes within distance
+    AmbientOcclusion {
+        distance: f64,
+    },
+}
+
+impl IntegratorKind {
+    pub fn create(self, max_depth: u32) -> Box<dyn Integrator> {
+        match self {
+            IntegratorKind::PathTracer => Box::new(PathTracer { max_depth }),
+            IntegratorKind::Normals => Box::new(Normals),
+            IntegratorKind::Uv => Box::new(Uvs),
+            IntegratorKind::Depth { far } => Box::new(Depth { far }),
+            IntegratorKind::Barycentrics => Box::new(Barycentrics),
+            IntegratorKind::MaterialId => Box::new(MaterialIds),
+            IntegratorKind::Albedo => Box::new(Albedo),
+            IntegratorKind::AmbientOcclusion { distance } => {
+                Box::new(AmbientOcclusion { distance })
+            }
+        }
+    }
+}
+
+// One bounce of a traced path
+pub enum PathEvent {
+    Scattered {
+        hit: Point3,
+        object_id: u32,
+        material_id: u32,
+        attenuation: Color,
+        scattered: Vec3,
+    },
+    Absorbed {
+        hit: Point3,
+        object_id: u32,
+        material_id: u32,
+    },
+    Escaped {
+        radiance: Color,
+    },
+}
+
+pub struct PathTracer {
+    pub max_depth: u32,
+}
+
+impl Integrator for PathTracer {
+    fn trace(&self, scene: &Scene, r: &Ray, mut path: Option<&mut Vec<PathEvent>>) -> PathSample {
+        let mut sample = PathSample {
+            color: Color::new(0.0, 0.0, 0.0),
+            direct: Color::new(0.0, 0.0, 0.0),
+            first_hit: None,
+        };
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = Ray::new(r.origin(), r.direction());
+        // Absorption of the medium the path is in. Media don't nest, leaving one goes back to
+        // clear air
+        let mut medium: Option<Color> = None;
+
+        for bounce in 0..self.max_depth {
+            let Some(rec) = intersect(scene, &ray, bounce) else {
+                let radiance = throughput * background(&ray);
+                if let Some(path) = path.as_mut() {
+                    path.push(PathEvent::Escaped { radiance });
+                }
+                sample.color = radiance;
+                if bounce <= 1 {
+                    sample.direct = radiance;
+                }
+                break;
+            };
+
+            if let Some(absorption) = medium {
+                let distance = rec.t * ray.direction().len();
+                let [r, g, b] = absorption.xyz().map(|a| (-a * distance).exp());
+                throughput = throughput * Color::new(r, g, b);
+            }
+
+            let scatter = rec.mat.scatter(&ray, &rec);
+            if bounce == 0 {
+                sample.first_hit = Some(first_hit(r, &rec, scatter.as_ref()));
+            }
+            if let Some(path) = path.as_mut() {
+                path.push(hit_event(&rec, scatter.as_ref()));
+            }
+
+            let Some(scatter) = scatter else {
+                break;
+            };
+            throughput = throughput * scatter.attenuation;
+
+            // Transmitted rays enter the surface's medium through a front face and leave it
+            // through a back face, reflected ones stay where they are
+            if scatter.scattered.direction().dot(&rec.normal) < 0.0 {
+                medium = match rec.front_face {
+                    true => rec.mat.absorption(),
+                    false => None,
+                };
+            }
+
+            ray = match (ray.wavelength(), scatter.scattered.wavelength()) {
+                (Some(wavelength), None) => scatter.scattered.with_wavelength(wavelength),
+                _ => scatter.scattered,
+            };
+        }
+
+        sample
+    }
+}
+
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn trace(&self, scene: &Scene, ray: &Ray, path: Option<&mut Vec<PathEvent>>) -> PathSample {
+        shade_first_hit(scene, ray, path, |rec, _| {
+            0.5 * (rec.normal + Color::new(1.0, 1.0, 1.0))
+        })
+    }
+}
+
+pub struct Uvs;
+
+impl Integrator for Uvs {
+    fn trace(&self, scene: &Scene, ray: &Ray, path: Option<&mut Vec<PathEvent>>) -> PathSample {
+        shade_first_hit(scene, ray, path, |rec, _| Color::new(rec.u, rec.v, 0.0))
+    }
+}
+
+pub struct Depth {
+    pub far: f64,
+}
+
+impl Integrator for Depth {
+    fn trace(&self, scene: &Scene, ray: &Ray, path: Option<&mut Vec<PathEvent>>) -> PathSample {
+        shade_first_hit(scene, ray, path, |_, hit| {
+            let value = (1.0 - hit.distance / self.far).max(0.0);
+            Color::new(value, value, value)
+        })
+    }
+}
+
+pub struct Barycentrics;
+
+impl Integrator for Barycentrics {
+    fn trace(&self, scene: &Scene, ray: &Ray, path: Option<&mut Vec<PathEvent>>) -> PathSample {
+        shade_first_hit(scene, ray, path, |rec, _| {
+            let (b1, b2) = rec.barycentric;
+            Color::new(1.0 - b1 - b2, b1, b2)
+        })
+    }
+}
+
+pub struct MaterialIds;
+
+impl Integrator for MaterialIds {
+    fn trace(&self, scene: &Scene, ray: &Ray, path: Option<&mut Vec<PathEvent>>) -> PathSample {
+        shade_first_hit(scene, ray, path, |_, hit| aov::id_color(hit.material_id))
+    }
+}
+
+pub struct Albedo;
+
+impl Integrator for Albedo {
+    fn trace(&self, scene: &Scene, ray: &Ray, path: Option<&mut Vec<PathEvent>>) -> PathSample {
+        shade_first_hit(scene, ray, path, |_, hit| hit.albedo)
+    }
+}
+
+pub struct AmbientOcclusion {
+    pub distance: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn trace(&self, scene: &Scene, ray: &Ray, path: Option<&mut Vec<PathEvent>>) -> PathSample {
+        shade_first_hit(scene, ray, path, |rec, _| {
+            // The shading normal faces the camera ray, so this samples the visible side
+            let direction =
+                Frame::from_normal(rec.normal).from_local(Vec3::rand_cosine_direction());
+
+            stats::record(|counters| counters.shadow_rays += 1);
+            match scene.hit(&Ray::new(rec.p, direction), 0.001, self.distance) {
+                Some(_) => Color::new(0.0, 0.0, 0.0),
+                None => Color::new(1.0, 1.0, 1.0),
+            }
+        })
+    }
+}
+
+pub fn background(r: &Ray) -> Color {
+    let unit_direction = r.direction().unit();
+    let a: f64 = (unit_direction.y() + 1.0) / 2.0;
+    (1.0 - a) * Color::new(1.0, 1.0, 1.0) + a * Color::new(0.5, 0.7, 1.0)
+}
+
+fn intersect(scene: &Scene, ray: &Ray, bounce: u32) -> Option<HitRecord> {
+    stats::record(|counters| match bounce {
+        0 => counters.primary_rays += 1,
+        _ => counters.secondary_rays += 1,
+    });
+    scene.hit(ray, 0.001, f64::INFINITY)
+}
+
+fn first_hit(r: &Ray, rec: &HitRecord, scatter: Option<&ScatterRecord>) -> FirstHit {
+    FirstHit {
+        albedo: scatter.map_or(Color::default(), |s| s.attenuation),
+        normal: rec.normal,
+        position: rec.p,
+        distance: (rec.p - r.origin()).len(),
+        object_id: rec.object_id,
+        material_id: rec.material_id,
+    }
+}
+
+fn hit_event(rec: &HitRecord, scatter: Option<&ScatterRecord>) -> PathEvent {
+    let (hit, object_id, material_id) = (rec.p, rec.object_id, rec.material_id);
+    match scatter {
+        Some(scatter) => PathEvent::Scattered {
+            hit,
+            object_id,
+            material_id,
+            attenuation: scatter.attenuation,
+            scattered: scatter.scattered.direction(),
+        },
+        None => PathEvent::Absorbed {
+            hit,
+            object_id,
+            material_id,
+        },
+    }
+}
+
+// The color of a diagnostic view, which still fills in the aovs like the path tracer would
+fn shade_first_hit(
+    scene: &Scene,
+    ray: &Ray,
+    path: Option<&mut Vec<PathEvent>>,
+    shade: impl FnOnce(&HitRecord, &FirstHit) -> Color,
+) -> PathSample {
+    let Some(rec) = intersect(scene, ray, 0) else {
+        if let Some(path) = path {
+            path.push(PathEvent::Escaped {
+                radiance: Color::default(),
+            });
+        }
+        return PathSample::default();
+    };
+
+    let scatter = rec.mat.scatter(ray, &rec);
+    let hit = first_hit(ray, &rec, scatter.as_ref());
+    if let Some(path) = path {
+        path.push(hit_event(&rec, scatter.as_ref()));
+    }
+
+    let color = shade(&rec, &hit);
+    PathSample {
+        color,
+        direct: color,
+        first_hit: Some(hit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::material::{Coated, Dielectric, Lambertian, Material, Metal, Mix};
+
+    fn assert_close(a: Color, b: Color) {
+        for (a, b) in a.xyz().into_iter().zip(b.xyz()) {
+            assert!((a - b).abs() < 1e-6, "{a} != {b}");
+        }
+    }
+
+    // Glass with a refraction index of 1 neither bends nor reflects head on rays, so only the
+    // absorption along the path is left
+    #[test]
+    fn absorption_depends_on_distance() {
+        let color = Color::new(0.8, 0.5, 0.2);
+        let glass = || Arc::new(Dielectric::new(1.0).with_transmittance(color, 2.0));
+        let tracer = PathTracer { max_depth: 10 };
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        // Wrapped in a mix or under a coat, the glass keeps its absorption
+        let mixed = Arc::new(Mix::constant(glass(), glass(), 0.5));
+        let coated = Arc::new(Coated::new(glass(), 1.0, 0.0));
+        let materials: [Arc<dyn Material>; 3] = [glass(), mixed, coated];
+        for mat in materials {
+            let mut scene = Scene::new();
+            scene.add_sphere(Point3::default(), 1.0, mat);
+            let through = tracer.trace(&scene, &ray, None).color;
+            assert_close(through, color * background(&ray));
+        }
+
+        let mut scene = Scene::new();
+        scene.add_sphere(Point3::default(), 1.0, glass());
+
+        // Bouncing off a mirror inside travels half the distance through the glass
+        let mirror = Arc::new(Metal::new(Color::new(1.0, 1.0, 1.0), 0.0));
+        scene.add_sphere(Point3::default(), 0.5, mirror);
+        let reflected = tracer.trace(&scene, &ray, None).color;
+        let [r, g, b] = color.xyz().map(f64::sqrt);
+        let up = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, 1.0));
+        assert_close(reflected, Color::new(r, g, b) * background(&up));
+    }
+
+    #[test]
+    fn normals_and_depth_of_a_sphere() {
+        let mut scene = Scene::new();
+        let grey = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        scene.add_sphere(Point3::default(), 1.0, grey);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let normal = Normals.trace(&scene, &ray, None).color;
+        assert_close(normal, Color::new(0.5, 0.5, 1.0));
+        let depth = Depth { far: 8.0 }.trace(&scene, &ray, None).color;
+        assert_close(depth, Color::new(0.5, 0.5, 0.5));
+
+        let miss = Ray::new(Point3::new(0.0, 2.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_close(Normals.trace(&scene, &miss, None).color, Color::default());
+    }
+
+    // Inside a dome of radius 2 every occlusion ray from the floor's center travels 2
+    #[test]
+    fn ambient_occlusion_distance() {
+        let mut scene = Scene::new();
+        let grey = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let floor = Point3::new(-10.0, 0.0, -10.0);
+        scene.add_quad(
+            floor,
+            Vec3::new(20.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 20.0),
+            grey.clone(),
+        );
+        scene.add_sphere(Point3::default(), 2.0, grey);
+        let ray = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+        for _ in 0..16 {
+            let near = AmbientOcclusion { distance: 1.5 };
+            assert_close(
+                near.trace(&scene, &ray, None).color,
+                Color::new(1.0, 1.0, 1.0),
+            );
+            let far = AmbientOcclusion { distance: 2.5 };
+            assert_close(far.trace(&scene, &ray, None).color, Color::default());
+        }
+    }
+}
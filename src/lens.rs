@@ -0,0 +1,310 @@
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+use crate::microfacet;
+use crate::vec3::{Point3, Vec3};
+
+// One spherical interface, or the aperture stop when the radius is 0. Lengths are in meters
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LensElement {
+    pub curvature_radius: f64,
+    // Distance to the next interface towards the film
+    pub thickness: f64,
+    // Index of refraction behind the interface, 0 or 1 for air
+    pub eta: f64,
+    pub aperture_radius: f64,
+}
+
+// Multi-element lens traced like pbrt's realistic camera. Lens space looks down +z with the
+// film at z = 0 and the elements at negative z, the first element faces the scene
+#[derive(Clone, Debug)]
+pub struct LensSystem {
+    elements: Vec<LensElement>,
+}
+
+impl LensSystem {
+    pub fn new(elements: Vec<LensElement>) -> io::Result<Self> {
+        if elements.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "lens has no elements"));
+        }
+        Ok(Self { elements })
+    }
+
+    // From the scene side towards the film
+    pub fn elements(&self) -> &[LensElement] {
+        &self.elements
+    }
+
+    // pbrt lens description, one element per line from the scene side: curvature radius,
+    // thickness, index of refraction and aperture diameter, all in mm. The last thickness
+    // is replaced when focusing
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+
+        let mut elements = Vec::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let values = line
+                .split_whitespace()
+                .map(|v| v.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid("bad number in lens description"))?;
+            let [radius, thickness, eta, aperture] = values[..] else {
+                return Err(invalid("lens elements need 4 values"));
+            };
+
+            elements.push(LensElement {
+                curvature_radius: radius * 0.001,
+                thickness: thickness * 0.001,
+                eta,
+                aperture_radius: aperture * 0.001 / 2.0,
+            });
+        }
+
+        Self::new(elements)
+    }
+
+    // Stops the aperture down (or up to what the description allows), diameter in mm
+    pub fn with_aperture_diameter(mut self, diameter: f64) -> Self {
+        for element in self.elements.iter_mut() {
+            if element.curvature_radius == 0.0 {
+                element.aperture_radius = (diameter * 0.001 / 2.0).min(element.aperture_radius);
+            }
+        }
+        self
+    }
+
+    pub fn rear_z(&self) -> f64 {
+        self.elements.last().unwrap().thickness
+    }
+
+    pub fn front_z(&self) -> f64 {
+        self.elements.iter().map(|e| e.thickness).sum()
+    }
+
+    pub fn rear_radius(&self) -> f64 {
+        self.elements.last().unwrap().aperture_radius
+    }
+
+    // Moves the lens so objects at focus_distance from the film are sharp, using the thick
+    // lens approximation. The lens racking in and out is what causes focus breathing
+    pub fn focused(&self, focus_distance: f64, film_diagonal: f64) -> Option<Self> {
+        let ((pz0, fz0), (pz1, _)) = self.thick_lens_approximation(film_diagonal)?;
+
+        let f = fz0 - pz0;
+        let z = -focus_distance;
+        let c = (pz1 - z - pz0) * (pz1 - z - 4.0 * f - pz0);
+        if c < 0.0 {
+            return None;
+        }
+        let delta = 0.5 * (pz1 - z + pz0 - c.sqrt());
+
+        let mut lens = self.clone();
+        lens.elements.last_mut().unwrap().thickness += delta;
+        Some(lens)
+    }
+
+    // Film side, then scene side
+    fn thick_lens_approximation(
+        &self,
+        film_diagonal: f64,
+    ) -> Option<(CardinalPoints, CardinalPoints)> {
+        // Rays parallel to the axis, close to it
+        let x = 0.001 * film_diagonal;
+
+        let scene_origin = Point3::new(x, 0.0, self.front_z() + 1.0);
+        let scene_ray = (scene_origin, Vec3::new(0.0, 0.0, -1.0));
+        let film_side = cardinal_points(scene_ray, self.trace_from_scene(scene_ray)?);
+
+        let film_origin = Point3::new(x, 0.0, self.rear_z() - 1.0);
+        let film_ray = (film_origin, Vec3::new(0.0, 0.0, 1.0));
+        let scene_side = cardinal_points(film_ray, self.trace_from_film(film_ray)?);
+
+        Some((film_side, scene_side))
+    }
+
+    // Ray leaving the front element for a ray starting on the film, both in camera space
+    // (z towards the scene). None when it's blocked by an element or the stop
+    pub fn trace_from_film(&self, (origin, direction): (Point3, Vec3)) -> Option<(Point3, Vec3)> {
+        let mut o = flip_z(origin);
+        let mut d = flip_z(direction);
+        let mut element_z = 0.0;
+
+        for (i, element) in self.elements.iter().enumerate().rev() {
+            element_z -= element.thickness;
+
+            let eta_t = match i {
+                0 => 1.0,
+                _ => air_as_one(self.elements[i - 1].eta),
+            };
+            (o, d) = self.cross(element, element_z, (o, d), air_as_one(element.eta), eta_t)?;
+        }
+
+        Some((flip_z(o), flip_z(d)))
+    }
+
+    pub fn trace_from_scene(&self, (origin, direction): (Point3, Vec3)) -> Option<(Point3, Vec3)> {
+        let mut o = flip_z(origin);
+        let mut d = flip_z(direction);
+        let mut element_z = -self.front_z();
+
+        for (i, element) in self.elements.iter().enumerate() {
+            let eta_i = match i {
+                0 => 1.0,
+                _ => air_as_one(self.elements[i - 1].eta),
+            };
+            (o, d) = self.cross(element, element_z, (o, d), eta_i, air_as_one(element.eta))?;
+
+            element_z += element.thickness;
+        }
+
+        Some((flip_z(o), flip_z(d)))
+    }
+
+    // Intersects and refracts through one interface in lens space
+    fn cross(
+        &self,
+        element: &LensElement,
+        element_z: f64,
+        (o, d): (Point3, Vec3),
+        eta_i: f64,
+        eta_t: f64,
+    ) -> Option<(Point3, Vec3)> {
+        let is_stop = element.curvature_radius == 0.0;
+
+        let (t, normal) = if is_stop {
+            // Rays running parallel to the stop never cross it
+            if d.z() == 0.0 {
+                return None;
+            }
+            let t = (element_z - o.z()) / d.z();
+            (t, Vec3::default())
+        } else {
+            let radius = element.curvature_radius;
+            intersect_spherical(radius, element_z + radius, (o, d))?
+        };
+        if t < 0.0 {
+            return None;
+        }
+
+        let p = o + t * d;
+        if p.x() * p.x() + p.y() * p.y() > element.aperture_radius * element.aperture_radius {
+            return None;
+        }
+
+        if is_stop {
+            return Some((p, d));
+        }
+        let refracted = microfacet::refract(&(-d).unit(), &normal, eta_t / eta_i)?;
+        Some((p, refracted))
+    }
+}
+
+// Principal plane and focal point z
+type CardinalPoints = (f64, f64);
+
+fn air_as_one(eta: f64) -> f64 {
+    if eta == 0.0 {
+        1.0
+    } else {
+        eta
+    }
+}
+
+fn flip_z(v: Vec3) -> Vec3 {
+    Vec3::new(v.x(), v.y(), -v.z())
+}
+
+fn intersect_spherical(radius: f64, z_center: f64, (o, d): (Point3, Vec3)) -> Option<(f64, Vec3)> {
+    let o = o - Vec3::new(0.0, 0.0, z_center);
+    let a = d.len_sq();
+    let b = 2.0 * d.dot(&o);
+    let c = o.len_sq() - radius * radius;
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let sqrt_d = discriminant.sqrt();
+    let (t0, t1) = ((-b - sqrt_d) / (2.0 * a), (-b + sqrt_d) / (2.0 * a));
+
+    // Which of the two hits is on the lens depends on the ray direction and the curvature
+    let use_closer = (d.z() > 0.0) ^ (radius < 0.0);
+    let t = if use_closer { t0.min(t1) } else { t0.max(t1) };
+    if t < 0.0 {
+        return None;
+    }
+
+    let n = (o + t * d).unit();
+    let n = if n.dot(&d) > 0.0 { -n } else { n };
+    Some((t, n))
+}
+
+// Where a ray parallel to the axis crosses it after the lens (the focal point) and where
+// its extension meets the incoming ray's height (the principal plane), as z in camera space
+fn cardinal_points(
+    (in_origin, _): (Point3, Vec3),
+    (out_origin, out_dir): (Point3, Vec3),
+) -> CardinalPoints {
+    let tf = -out_origin.x() / out_dir.x();
+    let fz = -(out_origin + tf * out_dir).z();
+    let tp = (in_origin.x() - out_origin.x()) / out_dir.x();
+    let pz = -(out_origin + tp * out_dir).z();
+    (pz, fz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // pbrt's dgauss.50mm
+    const DOUBLE_GAUSS: &str = "
+        # radius thickness ior aperture
+        29.475 3.76 1.67 25.2
+        84.83 0.12 1 25.2
+        19.275 4.025 1.67 23
+        40.77 3.275 1.699 23
+        12.75 5.705 1 18
+        0 4.5 0 17.1
+        -14.495 1.18 1.603 17
+        40.77 6.065 1.658 20
+        -20.385 0.19 1 20
+        437.065 3.22 1.717 20
+        -39.73 0 1 20
+    ";
+
+    #[test]
+    fn double_gauss_focal_length() {
+        let lens = LensSystem::parse(DOUBLE_GAUSS).unwrap();
+        let ((pz, fz), _) = lens.thick_lens_approximation(0.035).unwrap();
+        let focal_length = fz - pz;
+        assert!((focal_length - 0.050).abs() < 0.002, "{focal_length}");
+
+        // Focused at infinity the film sits on the focal point
+        let focused = lens.focused(1e6, 0.035).unwrap();
+        let (origin, direction) = focused
+            .trace_from_scene((Point3::new(0.001, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0)))
+            .unwrap();
+        let t = -origin.z() / direction.z();
+        assert!((origin + t * direction).x().abs() < 1e-5);
+    }
+
+    #[test]
+    fn rejects_empty_and_unfocusable_lenses() {
+        assert!(LensSystem::new(Vec::new()).is_err());
+        assert!(LensSystem::parse("# no elements").is_err());
+
+        // Closer than about four focal lengths there's no real image
+        let lens = LensSystem::parse(DOUBLE_GAUSS).unwrap();
+        assert!(lens.focused(0.1, 0.035).is_none());
+    }
+}
@@ -1,7 +1,26 @@
+pub mod animation;
+pub mod aov;
+pub mod aperture;
+pub mod camera;
+pub mod checkpoint;
+pub mod denoise;
+pub mod film;
+pub mod filter;
+pub mod image;
+pub mod integrator;
+pub mod lens;
 pub mod material;
+pub mod microfacet;
+pub mod progress;
 pub mod ray;
 pub mod raytracer;
+pub mod sampler;
 pub mod scene;
+pub mod spectrum;
 pub mod sphere;
+pub mod stats;
+pub mod texture;
+pub mod tonemap;
+pub mod triangle;
 pub mod utils;
 pub mod vec3;
@@ -1,26 +1,43 @@
+use std::io;
 use std::sync::Arc;
 use std::time::Instant;
 
 use rayrs::material::{Dielectric, Lambertian, Metal};
-use rayrs::raytracer::{Raytracer, RenderConfig};
+use rayrs::raytracer::{Progressive, Raytracer, RenderConfig};
+use rayrs::sampler::{self, IndependentSampler, SamplerKind};
 use rayrs::scene::Scene;
 use rayrs::vec3::Point3;
 use rayrs::utils::{self, Color};
 
 const IMAGE_WIDTH: u32 = 1200;
 const ASPECT_RATIO: f64 = 16.0 / 9.0;
+// The same scene on every run, so a resumed render adds samples of the one it started with
+const SCENE_SEED: u64 = 42;
 
 fn main() {
     const IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as u32;
 
+    // Continue an interrupted render from its last checkpoint
+    let resume = std::env::args().any(|arg| arg == "--resume");
+
     let render_config = RenderConfig {
         resolution: (IMAGE_WIDTH, IMAGE_HEIGHT),
         aspect_ratio: ASPECT_RATIO,
         samples_per_pixel: 500,
         max_depth: 50,
+        sampler: SamplerKind::Sobol,
+        progressive: Some(Progressive {
+            samples_per_pass: 16,
+            checkpoint: Some("render.checkpoint".into()),
+            resume,
+            ..Default::default()
+        }),
+        ..Default::default()
     };
 
+    let build_start = Instant::now();
     let mut world = Scene::new();
+    sampler::install(Box::new(IndependentSampler::new(SCENE_SEED)));
 
     let mat_ground = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
     world.add_sphere(Point3::new(0.0, -1000.0, 0.0), 1000.0, mat_ground);
@@ -59,13 +76,26 @@ fn main() {
     world.add_sphere(Point3::new(0.0, 1.0, 0.0), 1.0, material1);
     world.add_sphere(Point3::new(-4.0, 1.0, 0.0), 1.0, material2);
     world.add_sphere(Point3::new(4.0, 1.0, 0.0), 1.0, material3);
+    sampler::uninstall();
 
-    let raytracer = Raytracer::new(render_config, world);
+    let raytracer = match Raytracer::new(render_config, world) {
+        Ok(raytracer) => raytracer.with_build_time(build_start.elapsed()),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
 
-    //let start_time = Instant::now();
+    let start_time = Instant::now();
 
-    raytracer.render_p();
+    let written = raytracer
+        .render_p()
+        .and_then(|output| raytracer.write_image(&output.image, &mut io::stdout().lock()));
+    if let Err(err) = written {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
 
-    //let elapsed_time = start_time.elapsed();
-    //eprintln!("\rDone. Time taken: {:.2?}", elapsed_time);
+    let elapsed_time = start_time.elapsed();
+    eprintln!("\rDone. Time taken: {:.2?}", elapsed_time);
 }
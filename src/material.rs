@@ -1,8 +1,10 @@
 use std::sync::Arc;
 
+use crate::microfacet::{self, TrowbridgeReitz};
 use crate::ray::Ray;
 use crate::scene::HitRecord;
-use crate::sphere::Sphere;
+use crate::spectrum::{self, Dispersion};
+use crate::texture::{SolidColor, Texture};
 use crate::utils;
 use crate::utils::Color;
 use crate::vec3::Vec3;
@@ -14,6 +16,23 @@ pub struct ScatterRecord {
 
 pub trait Material: Send + Sync {
     fn scatter(&self, r_in: &Ray, hit_rec: &HitRecord) -> Option<ScatterRecord>;
+
+    // The material that shades this hit when it's another one, `Scene::hit` calls this before
+    // the mask test so that both see the same material
+    fn resolve(&self, _hit_rec: &HitRecord) -> Option<Arc<dyn Material>> {
+        None
+    }
+
+    // Cut-out regions are skipped by `Scene::hit` as if nothing was there
+    fn is_masked(&self, _hit_rec: &HitRecord) -> bool {
+        false
+    }
+
+    // Beer-Lambert coefficient of the medium behind the surface, the integrator applies it
+    // along every segment a path travels between entering and leaving
+    fn absorption(&self) -> Option<Color> {
+        None
+    }
 }
 
 pub struct Lambertian {
@@ -27,7 +46,7 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r_in: &Ray, hit_rec: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter(&self, _r_in: &Ray, hit_rec: &HitRecord) -> Option<ScatterRecord> {
         let mut scatter_dir = hit_rec.normal + Vec3::rand_unit_vec();
 
         if scatter_dir.is_near_zero() {
@@ -71,13 +90,100 @@ impl Material for Metal {
     }
 }
 
+// Rough conductor with GGX microfacets and complex IOR
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    distrib: TrowbridgeReitz,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Self {
+        Self::anisotropic(eta, k, roughness, roughness)
+    }
+
+    pub fn anisotropic(eta: Color, k: Color, roughness_u: f64, roughness_v: f64) -> Self {
+        Self {
+            eta,
+            k,
+            distrib: TrowbridgeReitz::from_roughness(roughness_u, roughness_v),
+        }
+    }
+
+    pub fn gold(roughness: f64) -> Self {
+        Self::new(
+            Color::new(0.143119, 0.374957, 1.44248),
+            Color::new(3.98316, 2.38572, 1.60322),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        Self::new(
+            Color::new(0.200438, 0.924033, 1.10221),
+            Color::new(3.91295, 2.45285, 2.14219),
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::new(
+            Color::new(1.65746, 0.880369, 0.521229),
+            Color::new(9.22387, 6.26952, 4.837),
+            roughness,
+        )
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, hit_rec: &HitRecord) -> Option<ScatterRecord> {
+        let frame = hit_rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction().unit());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let (wi, wm, weight) = self.distrib.sample_reflection(&wo)?;
+        let fresnel = microfacet::fresnel_complex_rgb(wo.dot(&wm), &self.eta, &self.k);
+
+        Some(ScatterRecord {
+            attenuation: weight * fresnel,
+            scattered: Ray::new(hit_rec.p, frame.from_local(wi)),
+        })
+    }
+}
+
 pub struct Dielectric {
     refraction_index: f64,
+    absorption: Option<Color>,
+    dispersion: Option<Dispersion>,
 }
 
 impl Dielectric {
     pub fn new(refraction_index: f64) -> Self {
-        Self { refraction_index }
+        Self {
+            refraction_index,
+            absorption: None,
+            dispersion: None,
+        }
+    }
+
+    // Beer-Lambert absorption coefficient per unit distance traveled inside
+    pub fn with_absorption(mut self, absorption: Color) -> Self {
+        self.absorption = Some(absorption);
+        self
+    }
+
+    // Absorption such that `color` is transmitted after traveling `distance` inside
+    pub fn with_transmittance(self, color: Color, distance: f64) -> Self {
+        let [r, g, b] = color.xyz().map(|c| -c.max(1e-6).ln() / distance);
+        self.with_absorption(Color::new(r, g, b))
+    }
+
+    // Samples a wavelength per scatter, overriding the constant refraction index
+    pub fn with_dispersion(mut self, dispersion: Dispersion) -> Self {
+        self.dispersion = Some(dispersion);
+        self
     }
 
     fn reflectance(cos: f64, ri: f64) -> f64 {
@@ -89,10 +195,26 @@ impl Dielectric {
 
 impl Material for Dielectric {
     fn scatter(&self, r_in: &Ray, hit_rec: &HitRecord) -> Option<ScatterRecord> {
+        // The sampled wavelength's color weight is applied once, when it's first picked, the
+        // integrator carries it along the rest of the path
+        let (wavelength, attenuation) = match (&self.dispersion, r_in.wavelength()) {
+            (None, _) => (None, Color::new(1.0, 1.0, 1.0)),
+            (Some(_), Some(wavelength)) => (Some(wavelength), Color::new(1.0, 1.0, 1.0)),
+            (Some(_), None) => {
+                let wavelength = utils::rand_range_f64(spectrum::LAMBDA_MIN, spectrum::LAMBDA_MAX);
+                (Some(wavelength), spectrum::wavelength_weight(wavelength))
+            }
+        };
+
+        let refraction_index = match (&self.dispersion, wavelength) {
+            (Some(dispersion), Some(wavelength)) => dispersion.ior(wavelength),
+            _ => self.refraction_index,
+        };
+
         let ri = if hit_rec.front_face {
-            1.0 / self.refraction_index
+            1.0 / refraction_index
         } else {
-            self.refraction_index
+            refraction_index
         };
 
         let unit_dir = r_in.direction().unit();
@@ -108,9 +230,671 @@ impl Material for Dielectric {
             unit_dir.refract(&hit_rec.normal, ri)
         };
 
+        let mut scattered = Ray::new(hit_rec.p, dir);
+        if let Some(wavelength) = wavelength {
+            scattered = scattered.with_wavelength(wavelength);
+        }
+
         Some(ScatterRecord {
-            attenuation: Color::new(1.0, 1.0, 1.0),
-            scattered: Ray::new(hit_rec.p, dir),
+            attenuation,
+            scattered,
         })
     }
+
+    fn absorption(&self) -> Option<Color> {
+        self.absorption
+    }
+}
+
+// Rough dielectric after Walter et al. 2007, "Microfacet Models for Refraction through Rough Surfaces"
+pub struct RoughDielectric {
+    refraction_index: f64,
+    distrib: TrowbridgeReitz,
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: f64, roughness: f64) -> Self {
+        Self::anisotropic(refraction_index, roughness, roughness)
+    }
+
+    pub fn anisotropic(refraction_index: f64, roughness_u: f64, roughness_v: f64) -> Self {
+        Self {
+            refraction_index,
+            distrib: TrowbridgeReitz::from_roughness(roughness_u, roughness_v),
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, hit_rec: &HitRecord) -> Option<ScatterRecord> {
+        // The normal always faces the incoming ray, so eta is relative to the side we came from
+        let eta = if hit_rec.front_face {
+            self.refraction_index
+        } else {
+            1.0 / self.refraction_index
+        };
+
+        let frame = hit_rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction().unit());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let (wi, weight) = self.distrib.sample_dielectric(&wo, eta)?;
+
+        Some(ScatterRecord {
+            attenuation: Color::new(weight, weight, weight),
+            scattered: Ray::new(hit_rec.p, frame.from_local(wi)),
+        })
+    }
+}
+
+// Disney principled BSDF after Burley 2012, "Physically Based Shading at Disney",
+// with the clearcoat using GGX instead of GTR1 and transmission as a rough dielectric
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub anisotropic: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>,
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub sheen_tint: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_gloss: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub ior: f64,
+}
+
+impl Principled {
+    pub fn new(base_color: Arc<dyn Texture>) -> Self {
+        Self {
+            base_color,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        let scalar = |v| Arc::new(SolidColor::scalar(v)) as Arc<dyn Texture>;
+
+        Self {
+            base_color: scalar(0.8),
+            metallic: scalar(0.0),
+            roughness: scalar(0.5),
+            anisotropic: scalar(0.0),
+            specular: scalar(0.5),
+            specular_tint: scalar(0.0),
+            sheen: scalar(0.0),
+            sheen_tint: scalar(0.5),
+            clearcoat: scalar(0.0),
+            clearcoat_gloss: scalar(1.0),
+            transmission: scalar(0.0),
+            ior: 1.5,
+        }
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, hit_rec: &HitRecord) -> Option<ScatterRecord> {
+        let (u, v, p) = (hit_rec.u, hit_rec.v, &hit_rec.p);
+        let scalar = |t: &Arc<dyn Texture>| t.value_f64(u, v, p).clamp(0.0, 1.0);
+
+        let base_color = self.base_color.value(u, v, p);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let anisotropic = scalar(&self.anisotropic);
+        let specular = scalar(&self.specular);
+        let specular_tint = scalar(&self.specular_tint);
+        let sheen = scalar(&self.sheen);
+        let sheen_tint = scalar(&self.sheen_tint);
+        let clearcoat = scalar(&self.clearcoat);
+        let clearcoat_gloss = scalar(&self.clearcoat_gloss);
+        let transmission = scalar(&self.transmission);
+
+        let frame = hit_rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction().unit());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let white = Color::new(1.0, 1.0, 1.0);
+        let lerp = |a: Color, b: Color, t: f64| (1.0 - t) * a + t * b;
+
+        let lum = utils::luminance(base_color);
+        let tint = if lum > 0.0 { base_color / lum } else { white };
+        let spec0 = lerp(
+            specular * 0.08 * lerp(white, tint, specular_tint),
+            base_color,
+            metallic,
+        );
+        let sheen_color = sheen * lerp(white, tint, sheen_tint);
+
+        // Lobe weights, the transmissive part carries its own Fresnel reflection. What the
+        // clearcoat reflects doesn't reach the layers below, and what the specular reflects
+        // doesn't reach the diffuse base
+        let clearcoat_w = 0.25 * clearcoat;
+        let coat_fresnel = clearcoat_w * microfacet::fresnel_schlick(0.04 * white, wo.z()).x();
+        let spec_fresnel = microfacet::fresnel_schlick(spec0, wo.z());
+        let diffuse_w = (1.0 - coat_fresnel) * (1.0 - metallic) * (1.0 - transmission);
+        let transmission_w = (1.0 - coat_fresnel) * (1.0 - metallic) * transmission;
+        let specular_w = (1.0 - coat_fresnel) - transmission_w;
+
+        // Pick one lobe proportionally to a rough estimate of its albedo
+        let estimates = [
+            diffuse_w
+                * utils::luminance(white - spec_fresnel)
+                * (lum + utils::luminance(sheen_color)),
+            specular_w * utils::luminance(spec_fresnel),
+            transmission_w,
+            coat_fresnel,
+        ];
+        let total: f64 = estimates.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+
+        let mut xi = utils::rand_f64() * total;
+        let lobe = estimates
+            .iter()
+            .position(|&e| {
+                xi -= e;
+                xi < 0.0
+            })
+            .unwrap_or(estimates.len() - 1);
+        let prob = estimates[lobe] / total;
+
+        let aspect = (1.0 - 0.9 * anisotropic).sqrt();
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness);
+        let distrib = TrowbridgeReitz::new(alpha / aspect, alpha * aspect);
+
+        let (wi, weight) = match lobe {
+            0 => {
+                let wi = Vec3::rand_cosine_direction();
+                let cos_d = wi.dot(&(wi + wo).unit());
+
+                let fd90 = 0.5 + 2.0 * roughness * cos_d * cos_d;
+                let fl = 1.0 + (fd90 - 1.0) * (1.0 - wi.z()).powi(5);
+                let fv = 1.0 + (fd90 - 1.0) * (1.0 - wo.z()).powi(5);
+
+                // Retro-reflection and sheen push the response past 1 at grazing angles, where
+                // clamping it keeps the lobe from adding energy
+                let sheen = (1.0 - cos_d).powi(5) * sheen_color;
+                let [r, g, b] = (fl * fv * base_color + sheen).xyz().map(|c| c.min(1.0));
+
+                (wi, diffuse_w * (white - spec_fresnel) * Color::new(r, g, b))
+            }
+            1 => {
+                let (wi, wm, g) = distrib.sample_reflection(&wo)?;
+                let fresnel = microfacet::fresnel_schlick(spec0, wo.dot(&wm));
+                (wi, specular_w * g * fresnel)
+            }
+            2 => {
+                let eta = if hit_rec.front_face {
+                    self.ior
+                } else {
+                    1.0 / self.ior
+                };
+                let (wi, masking) =
+                    TrowbridgeReitz::new(alpha, alpha).sample_dielectric(&wo, eta)?;
+
+                // Tint each crossing so entering and leaving gives the base color
+                let [r, g, b] = base_color.xyz().map(f64::sqrt);
+                let tint = if wi.z() < 0.0 {
+                    Color::new(r, g, b)
+                } else {
+                    white
+                };
+                (wi, transmission_w * masking * tint)
+            }
+            _ => {
+                let alpha = (1.0 - clearcoat_gloss) * 0.1 + clearcoat_gloss * 0.001;
+                let (wi, wm, g) = TrowbridgeReitz::new(alpha, alpha).sample_reflection(&wo)?;
+                let fresnel = microfacet::fresnel_schlick(0.04 * white, wo.dot(&wm));
+                (wi, clearcoat_w * g * fresnel)
+            }
+        };
+
+        Some(ScatterRecord {
+            attenuation: weight / prob,
+            scattered: Ray::new(hit_rec.p, frame.from_local(wi)),
+        })
+    }
+}
+
+// Dielectric clearcoat over any base material, the light bouncing between the coat and
+// the base is followed with a random walk in the layer
+pub struct Coated {
+    base: Arc<dyn Material>,
+    ior: f64,
+    distrib: TrowbridgeReitz,
+    absorption: Color,
+    thickness: f64,
+}
+
+impl Coated {
+    const MAX_BOUNCES: u32 = 10;
+
+    pub fn new(base: Arc<dyn Material>, ior: f64, roughness: f64) -> Self {
+        Self {
+            base,
+            ior,
+            distrib: TrowbridgeReitz::from_roughness(roughness, roughness),
+            absorption: Color::default(),
+            thickness: 0.0,
+        }
+    }
+
+    // Tinted coat, absorption is per unit distance traveled inside a layer of given thickness
+    pub fn with_absorption(mut self, absorption: Color, thickness: f64) -> Self {
+        self.absorption = absorption;
+        self.thickness = thickness;
+        self
+    }
+
+    fn transmittance(&self, cos_theta: f64) -> Color {
+        let distance = self.thickness / cos_theta.abs().max(1e-4);
+        let [r, g, b] = self.absorption.xyz().map(|a| (-a * distance).exp());
+        Color::new(r, g, b)
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, r_in: &Ray, hit_rec: &HitRecord) -> Option<ScatterRecord> {
+        let frame = hit_rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction().unit());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let (wi, weight) = self.distrib.sample_dielectric(&wo, self.ior)?;
+        let mut attenuation = Color::new(weight, weight, weight);
+        if wi.z() > 0.0 {
+            return Some(ScatterRecord {
+                attenuation,
+                scattered: Ray::new(hit_rec.p, frame.from_local(wi)),
+            });
+        }
+
+        // Seen from inside the layer the coat faces down, mirroring z keeps +z towards the ray
+        let mirror = |w: Vec3| Vec3::new(w.x(), w.y(), -w.z());
+
+        let mut w = wi;
+        for _ in 0..Self::MAX_BOUNCES {
+            attenuation = attenuation * self.transmittance(w.z());
+
+            let base_in = Ray::new(hit_rec.p, frame.from_local(w));
+            let base = self.base.scatter(&base_in, hit_rec)?;
+            attenuation = attenuation * base.attenuation;
+
+            let up = frame.to_local(base.scattered.direction().unit());
+            if up.z() <= 0.0 {
+                // Transmitted through the base, the coat doesn't see it again
+                return Some(ScatterRecord {
+                    attenuation,
+                    scattered: base.scattered,
+                });
+            }
+            attenuation = attenuation * self.transmittance(up.z());
+
+            let (wi, weight) = self
+                .distrib
+                .sample_dielectric(&mirror(-up), 1.0 / self.ior)?;
+            attenuation = weight * attenuation;
+
+            let wi = mirror(wi);
+            if wi.z() > 0.0 {
+                return Some(ScatterRecord {
+                    attenuation,
+                    scattered: Ray::new(hit_rec.p, frame.from_local(wi)),
+                });
+            }
+            w = wi;
+        }
+
+        None
+    }
+
+    fn is_masked(&self, hit_rec: &HitRecord) -> bool {
+        self.base.is_masked(hit_rec)
+    }
+
+    // Paths transmitted through the base end up in its medium, the coat's own absorption is
+    // applied in scatter
+    fn absorption(&self) -> Option<Color> {
+        self.base.absorption()
+    }
+}
+
+// Picks one of two materials per hit, weight 0 is all `a` and 1 is all `b`. The pick is
+// made once in `Scene::hit`, inside another material it's made per call instead
+pub struct Mix {
+    a: Arc<dyn Material>,
+    b: Arc<dyn Material>,
+    weight: Arc<dyn Texture>,
+}
+
+impl Mix {
+    pub fn new(a: Arc<dyn Material>, b: Arc<dyn Material>, weight: Arc<dyn Texture>) -> Self {
+        Self { a, b, weight }
+    }
+
+    pub fn constant(a: Arc<dyn Material>, b: Arc<dyn Material>, weight: f64) -> Self {
+        Self::new(a, b, Arc::new(SolidColor::scalar(weight)))
+    }
+
+    fn pick(&self, hit_rec: &HitRecord) -> &Arc<dyn Material> {
+        let weight = self
+            .weight
+            .value_f64(hit_rec.u, hit_rec.v, &hit_rec.p)
+            .clamp(0.0, 1.0);
+
+        if utils::rand_f64() < weight {
+            &self.b
+        } else {
+            &self.a
+        }
+    }
+}
+
+impl Material for Mix {
+    fn scatter(&self, r_in: &Ray, hit_rec: &HitRecord) -> Option<ScatterRecord> {
+        self.pick(hit_rec).scatter(r_in, hit_rec)
+    }
+
+    fn resolve(&self, hit_rec: &HitRecord) -> Option<Arc<dyn Material>> {
+        let picked = self.pick(hit_rec);
+        Some(picked.resolve(hit_rec).unwrap_or_else(|| picked.clone()))
+    }
+
+    fn is_masked(&self, hit_rec: &HitRecord) -> bool {
+        self.pick(hit_rec).is_masked(hit_rec)
+    }
+
+    // The integrator usually gets the picked material from `Scene::hit`. Inside another
+    // material there's no pick to go by, so it's only kept when both agree
+    fn absorption(&self) -> Option<Color> {
+        match (self.a.absorption(), self.b.absorption()) {
+            (Some(a), Some(b)) if a.xyz() == b.xyz() => Some(a),
+            _ => None,
+        }
+    }
+}
+
+// Perturbs the shading normal of any material with a tangent space normal map
+pub struct NormalMap {
+    base: Arc<dyn Material>,
+    map: Arc<dyn Texture>,
+}
+
+impl NormalMap {
+    // The map is expected in linear [0, 1] encoding, see `ImageTexture::open_linear`
+    pub fn new(base: Arc<dyn Material>, map: Arc<dyn Texture>) -> Self {
+        Self { base, map }
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(&self, r_in: &Ray, hit_rec: &HitRecord) -> Option<ScatterRecord> {
+        let encoded = self.map.value(hit_rec.u, hit_rec.v, &hit_rec.p);
+        let local = 2.0 * encoded - Vec3::new(1.0, 1.0, 1.0);
+        let normal = hit_rec.shading_frame().from_local(local);
+
+        self.base.scatter(r_in, &perturb(hit_rec, normal))
+    }
+
+    fn is_masked(&self, hit_rec: &HitRecord) -> bool {
+        self.base.is_masked(hit_rec)
+    }
+
+    fn absorption(&self) -> Option<Color> {
+        self.base.absorption()
+    }
+}
+
+// Perturbs the shading normal of any material by the gradient of a height texture
+pub struct BumpMap {
+    base: Arc<dyn Material>,
+    height: Arc<dyn Texture>,
+    strength: f64,
+}
+
+impl BumpMap {
+    const DELTA: f64 = 1e-3;
+
+    pub fn new(base: Arc<dyn Material>, height: Arc<dyn Texture>, strength: f64) -> Self {
+        Self {
+            base,
+            height,
+            strength,
+        }
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(&self, r_in: &Ray, hit_rec: &HitRecord) -> Option<ScatterRecord> {
+        let (u, v, p, d) = (hit_rec.u, hit_rec.v, hit_rec.p, Self::DELTA);
+
+        let h = self.height.value_f64(u, v, &p);
+        let dh_du = (self.height.value_f64(u + d, v, &(p + d * hit_rec.tangent)) - h) / d;
+        let dh_dv = (self
+            .height
+            .value_f64(u, v + d, &(p + d * hit_rec.bitangent))
+            - h)
+            / d;
+
+        let normal =
+            hit_rec.normal - self.strength * (dh_du * hit_rec.tangent + dh_dv * hit_rec.bitangent);
+
+        self.base.scatter(r_in, &perturb(hit_rec, normal))
+    }
+
+    fn is_masked(&self, hit_rec: &HitRecord) -> bool {
+        self.base.is_masked(hit_rec)
+    }
+
+    fn absorption(&self) -> Option<Color> {
+        self.base.absorption()
+    }
+}
+
+// Opacity mask over any material, masked out regions let rays through without a bounce
+pub struct AlphaMask {
+    base: Arc<dyn Material>,
+    opacity: Arc<dyn Texture>,
+    cutoff: Option<f64>,
+}
+
+impl AlphaMask {
+    // Partial opacity lets rays through stochastically
+    pub fn new(base: Arc<dyn Material>, opacity: Arc<dyn Texture>) -> Self {
+        Self {
+            base,
+            opacity,
+            cutoff: None,
+        }
+    }
+
+    // Anything below the cutoff is fully transparent, the rest fully opaque
+    pub fn with_cutoff(base: Arc<dyn Material>, opacity: Arc<dyn Texture>, cutoff: f64) -> Self {
+        Self {
+            base,
+            opacity,
+            cutoff: Some(cutoff),
+        }
+    }
+}
+
+impl Material for AlphaMask {
+    fn scatter(&self, r_in: &Ray, hit_rec: &HitRecord) -> Option<ScatterRecord> {
+        self.base.scatter(r_in, hit_rec)
+    }
+
+    fn is_masked(&self, hit_rec: &HitRecord) -> bool {
+        let opacity = self.opacity.value_f64(hit_rec.u, hit_rec.v, &hit_rec.p);
+
+        let masked = match self.cutoff {
+            Some(cutoff) => opacity < cutoff,
+            None => opacity < 1.0 && utils::rand_f64() >= opacity,
+        };
+
+        masked || self.base.is_masked(hit_rec)
+    }
+
+    fn absorption(&self) -> Option<Color> {
+        self.base.absorption()
+    }
+}
+
+// Rebuilds the shading frame around a new normal, keeping it on the geometric side
+fn perturb(hit_rec: &HitRecord, normal: Vec3) -> HitRecord {
+    let mut rec = hit_rec.clone();
+    if normal.is_near_zero() || normal.dot(&hit_rec.normal) <= 0.0 {
+        return rec;
+    }
+
+    rec.normal = normal.unit();
+    rec.set_tangent(hit_rec.tangent);
+    rec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{Hittable, Scene};
+    use crate::vec3::Point3;
+
+    // Average throughput of a single scattering event, with absorbed rays counting as zero
+    fn furnace(mat: Arc<dyn Material>, cos_theta: f64, n: u32) -> f64 {
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let r_in = Ray::new(
+            Point3::new(0.0, 0.0, 1.0),
+            Vec3::new(sin_theta, 0.0, -cos_theta),
+        );
+
+        let mut hit_rec = HitRecord {
+            p: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::default(),
+            tangent: Vec3::default(),
+            bitangent: Vec3::default(),
+            mat: mat.clone(),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: false,
+            barycentric: (0.0, 0.0),
+            object_id: 0,
+            material_id: 0,
+        };
+        hit_rec.set_face_normal(&r_in, Vec3::new(0.0, 0.0, 1.0));
+        hit_rec.set_tangent(Vec3::new(1.0, 0.0, 0.0));
+
+        let total: f64 = (0..n)
+            .filter_map(|_| mat.scatter(&r_in, &hit_rec))
+            .map(|s| s.attenuation.x())
+            .sum();
+
+        total / n as f64
+    }
+
+    #[test]
+    fn rough_dielectric_white_furnace() {
+        for roughness in [0.0, 0.2, 0.5, 0.8] {
+            let mat: Arc<dyn Material> = Arc::new(RoughDielectric::new(1.5, roughness));
+            for cos_theta in [1.0, 0.7, 0.3] {
+                let albedo = furnace(mat.clone(), cos_theta, 20_000);
+
+                // Never gains energy, and single scattering only loses a small fraction
+                assert!(albedo <= 1.0 + 1e-9, "roughness {roughness}: {albedo}");
+                assert!(albedo > 0.75, "roughness {roughness}: {albedo}");
+                if roughness == 0.0 {
+                    assert!((albedo - 1.0).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn principled_white_furnace() {
+        let scalar = |v| Arc::new(SolidColor::scalar(v)) as Arc<dyn Texture>;
+        let white = || Principled::new(scalar(1.0));
+        let materials = [
+            white(),
+            Principled {
+                sheen: scalar(1.0),
+                roughness: scalar(1.0),
+                ..white()
+            },
+            Principled {
+                clearcoat: scalar(1.0),
+                roughness: scalar(0.0),
+                ..white()
+            },
+            Principled {
+                metallic: scalar(0.5),
+                transmission: scalar(0.5),
+                ..white()
+            },
+        ];
+
+        for mat in materials {
+            let mat: Arc<dyn Material> = Arc::new(mat);
+            for cos_theta in [1.0, 0.5, 0.1] {
+                let albedo = furnace(mat.clone(), cos_theta, 50_000);
+                assert!(albedo <= 1.01, "cos {cos_theta}: {albedo}");
+            }
+        }
+    }
+
+    #[test]
+    fn mixed_masks_show_the_surface_behind() {
+        let clear = || Arc::new(SolidColor::scalar(0.0)) as Arc<dyn Texture>;
+        let grey = || Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))) as Arc<dyn Material>;
+        let threshold = Arc::new(AlphaMask::with_cutoff(grey(), clear(), 0.5));
+        let stochastic = Arc::new(AlphaMask::new(grey(), clear()));
+
+        let mut scene = Scene::new();
+        let (u, v) = (Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0));
+        let mix = Arc::new(Mix::constant(threshold, stochastic, 0.5));
+        scene.add_quad(Point3::new(-1.0, -1.0, 0.0), u, v, mix);
+        scene.add_quad(Point3::new(-1.0, -1.0, -1.0), u, v, grey());
+
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        for _ in 0..100 {
+            let rec = scene.hit(&ray, 0.001, f64::INFINITY).unwrap();
+            assert!((rec.t - 2.0).abs() < 1e-9);
+        }
+    }
+
+    // The pick is made once per hit, so the opaque branch is never shaded by the masked one
+    #[test]
+    fn mix_picks_once_per_hit() {
+        let red = Color::new(1.0, 0.0, 0.0);
+        let clear = Arc::new(SolidColor::scalar(0.0));
+        let blue = Arc::new(Lambertian::new(Color::new(0.0, 0.0, 1.0)));
+        let masked = Arc::new(AlphaMask::with_cutoff(blue, clear, 0.5));
+        let mix = Arc::new(Mix::constant(Arc::new(Lambertian::new(red)), masked, 0.5));
+
+        let mut scene = Scene::new();
+        let (u, v) = (Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0));
+        scene.add_quad(Point3::new(-1.0, -1.0, 0.0), u, v, mix);
+        let green = Arc::new(Lambertian::new(Color::new(0.0, 1.0, 0.0)));
+        scene.add_quad(Point3::new(-1.0, -1.0, -1.0), u, v, green);
+
+        // Away from the diagonal, where both triangles of the quad would get a pick
+        let ray = Ray::new(Point3::new(0.5, -0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut front = 0;
+        for _ in 0..1000 {
+            let rec = scene.hit(&ray, 0.001, f64::INFINITY).unwrap();
+            let color = rec.mat.scatter(&ray, &rec).unwrap().attenuation;
+            if (rec.t - 1.0).abs() < 1e-9 {
+                assert_eq!(color.xyz(), red.xyz());
+                front += 1;
+            } else {
+                assert_eq!(color.y(), 1.0);
+            }
+        }
+        assert!((400..600).contains(&front), "{front}");
+    }
 }
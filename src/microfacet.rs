@@ -0,0 +1,387 @@
+use std::f64::consts::PI;
+use std::ops::{Add, Div, Mul, Sub};
+
+use crate::sampler;
+use crate::utils::{self, Color};
+use crate::vec3::Vec3;
+
+// Orthonormal shading frame, z is the normal
+#[derive(Copy, Clone)]
+pub struct Frame {
+    s: Vec3,
+    t: Vec3,
+    n: Vec3,
+}
+
+impl Frame {
+    pub fn new(s: Vec3, t: Vec3, n: Vec3) -> Self {
+        Self { s, t, n }
+    }
+
+    // Duff et al. 2017, "Building an Orthonormal Basis, Revisited"
+    pub fn from_normal(n: Vec3) -> Self {
+        let sign = 1.0_f64.copysign(n.z());
+        let a = -1.0 / (sign + n.z());
+        let b = n.x() * n.y() * a;
+
+        let s = Vec3::new(1.0 + sign * n.x() * n.x() * a, sign * b, -sign * n.x());
+        let t = Vec3::new(b, sign + n.y() * n.y() * a, -n.y());
+
+        Self { s, t, n }
+    }
+
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(&self.s), v.dot(&self.t), v.dot(&self.n))
+    }
+
+    pub fn from_local(&self, v: Vec3) -> Vec3 {
+        v.x() * self.s + v.y() * self.t + v.z() * self.n
+    }
+}
+
+// Trowbridge-Reitz (GGX) distribution with Smith masking, in the local frame
+#[derive(Copy, Clone)]
+pub struct TrowbridgeReitz {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        Self { alpha_x, alpha_y }
+    }
+
+    // Perceptually linear roughness in [0, 1], alpha = roughness^2
+    pub fn from_roughness(roughness_x: f64, roughness_y: f64) -> Self {
+        Self::new(
+            Self::roughness_to_alpha(roughness_x),
+            Self::roughness_to_alpha(roughness_y),
+        )
+    }
+
+    pub fn roughness_to_alpha(roughness: f64) -> f64 {
+        let r = roughness.clamp(0.0, 1.0);
+        r * r
+    }
+
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        let cos2_theta = wm.z() * wm.z();
+        let sin2_theta = (1.0 - cos2_theta).max(0.0);
+        let tan2_theta = sin2_theta / cos2_theta;
+        if !tan2_theta.is_finite() {
+            return 0.0;
+        }
+
+        let cos4_theta = cos2_theta * cos2_theta;
+        if cos4_theta < 1e-16 {
+            return 0.0;
+        }
+
+        let (cos2_phi, sin2_phi) = phi_terms(wm, sin2_theta);
+        let e = tan2_theta
+            * (cos2_phi / (self.alpha_x * self.alpha_x) + sin2_phi / (self.alpha_y * self.alpha_y));
+
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos4_theta * (1.0 + e) * (1.0 + e))
+    }
+
+    pub fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2_theta = w.z() * w.z();
+        let sin2_theta = (1.0 - cos2_theta).max(0.0);
+        let tan2_theta = sin2_theta / cos2_theta;
+        if !tan2_theta.is_finite() {
+            return 0.0;
+        }
+
+        let (cos2_phi, sin2_phi) = phi_terms(w, sin2_theta);
+        let alpha2 =
+            cos2_phi * self.alpha_x * self.alpha_x + sin2_phi * self.alpha_y * self.alpha_y;
+
+        ((1.0 + alpha2 * tan2_theta).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Distribution of normals visible from w
+    pub fn d_visible(&self, w: &Vec3, wm: &Vec3) -> f64 {
+        self.g1(w) / w.z().abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    // Samples a mirrored direction off a visible microfacet, returning it with the
+    // microfacet normal and the G2 / G1 throughput of visible normal sampling
+    pub fn sample_reflection(&self, wo: &Vec3) -> Option<(Vec3, Vec3, f64)> {
+        if self.effectively_smooth() {
+            let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+            return Some((wi, Vec3::new(0.0, 0.0, 1.0), 1.0));
+        }
+
+        let (u1, u2) = sampler::get_2d();
+        let wm = self.sample_wm(wo, u1, u2);
+        let wi = (-*wo).reflect(&wm);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+
+        Some((wi, wm, self.g(wo, &wi) / self.g1(wo)))
+    }
+
+    // Samples reflection or refraction through a dielectric interface, eta = n_t / n_i.
+    // Choosing between the two by the Fresnel term cancels it out, leaving G2 / G1
+    pub fn sample_dielectric(&self, wo: &Vec3, eta: f64) -> Option<(Vec3, f64)> {
+        let wm = if self.effectively_smooth() {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            let (u1, u2) = sampler::get_2d();
+            self.sample_wm(wo, u1, u2)
+        };
+
+        let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+        let wi = if reflectance > utils::rand_f64() {
+            let wi = (-*wo).reflect(&wm);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            let wi = refract(wo, &wm, eta)?;
+            if wi.z() >= 0.0 {
+                return None;
+            }
+            wi
+        };
+
+        if self.effectively_smooth() {
+            Some((wi, 1.0))
+        } else {
+            Some((wi, self.g(wo, &wi) / self.g1(wo)))
+        }
+    }
+
+    // Heitz 2018, "Sampling the GGX Distribution of Visible Normals"
+    pub fn sample_wm(&self, w: &Vec3, u1: f64, u2: f64) -> Vec3 {
+        let mut wh = Vec3::new(self.alpha_x * w.x(), self.alpha_y * w.y(), w.z()).unit();
+        if wh.z() < 0.0 {
+            wh = -wh;
+        }
+
+        let t1 = if wh.z() < 0.99999 {
+            Vec3::new(0.0, 0.0, 1.0).cross(&wh).unit()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let px = r * phi.cos();
+        let py = r * phi.sin();
+
+        let h = (1.0 - px * px).sqrt();
+        let s = (1.0 + wh.z()) / 2.0;
+        let py = (1.0 - s) * h + s * py;
+
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+        let nh = px * t1 + py * t2 + pz * wh;
+
+        Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        )
+        .unit()
+    }
+}
+
+fn phi_terms(w: &Vec3, sin2_theta: f64) -> (f64, f64) {
+    if sin2_theta <= 0.0 {
+        return (1.0, 0.0);
+    }
+
+    let cos2_phi = (w.x() * w.x() / sin2_theta).clamp(0.0, 1.0);
+    let sin2_phi = (w.y() * w.y() / sin2_theta).clamp(0.0, 1.0);
+    (cos2_phi, sin2_phi)
+}
+
+#[derive(Copy, Clone)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    fn norm(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn sqrt(self) -> Self {
+        let n = self.norm().sqrt();
+        if n == 0.0 {
+            return Complex::new(0.0, 0.0);
+        }
+
+        let t1 = (0.5 * (n + self.re.abs())).sqrt();
+        let t2 = 0.5 * self.im / t1;
+        if self.re >= 0.0 {
+            Complex::new(t1, t2)
+        } else {
+            Complex::new(t2.abs(), t1.copysign(self.im))
+        }
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, c: Complex) -> Complex {
+        Complex::new(self.re + c.re, self.im + c.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, c: Complex) -> Complex {
+        Complex::new(self.re - c.re, self.im - c.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, c: Complex) -> Complex {
+        Complex::new(
+            self.re * c.re - self.im * c.im,
+            self.re * c.im + self.im * c.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+
+    fn div(self, c: Complex) -> Complex {
+        let scale = 1.0 / c.norm();
+        Complex::new(
+            scale * (self.re * c.re + self.im * c.im),
+            scale * (self.im * c.re - self.re * c.im),
+        )
+    }
+}
+
+// Unpolarized Fresnel reflectance of a conductor with complex IOR eta + ik
+pub fn fresnel_complex(cos_theta_i: f64, eta: f64, k: f64) -> f64 {
+    let cos_theta_i = Complex::new(cos_theta_i.clamp(0.0, 1.0), 0.0);
+    let eta = Complex::new(eta, k);
+    let one = Complex::new(1.0, 0.0);
+
+    let sin2_theta_i = one - cos_theta_i * cos_theta_i;
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    let cos_theta_t = (one - sin2_theta_t).sqrt();
+
+    let r_parl = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perp = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+
+    (r_parl.norm() + r_perp.norm()) / 2.0
+}
+
+pub fn fresnel_complex_rgb(cos_theta_i: f64, eta: &Color, k: &Color) -> Color {
+    let [er, eg, eb] = eta.xyz();
+    let [kr, kg, kb] = k.xyz();
+
+    Color::new(
+        fresnel_complex(cos_theta_i, er, kr),
+        fresnel_complex(cos_theta_i, eg, kg),
+        fresnel_complex(cos_theta_i, eb, kb),
+    )
+}
+
+pub fn fresnel_schlick(f0: Color, cos_theta: f64) -> Color {
+    let m = (1.0 - cos_theta).clamp(0.0, 1.0);
+    f0 + (Color::new(1.0, 1.0, 1.0) - f0) * m.powi(5)
+}
+
+// Unpolarized Fresnel reflectance of a dielectric interface, eta = n_t / n_i
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let mut cos_theta_i = cos_theta_i.clamp(-1.0, 1.0);
+    let mut eta = eta;
+    if cos_theta_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_theta_i = -cos_theta_i;
+    }
+
+    let sin2_theta_i = 1.0 - cos_theta_i * cos_theta_i;
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    let r_parl = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perp = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+
+    (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+// Refracts w (pointing away from the surface) through n, None on total internal reflection
+pub fn refract(w: &Vec3, n: &Vec3, eta: f64) -> Option<Vec3> {
+    let mut cos_theta_i = n.dot(w);
+    let mut eta = eta;
+    let mut n = *n;
+    if cos_theta_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_theta_i = -cos_theta_i;
+        n = -n;
+    }
+
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    Some(-*w / eta + (cos_theta_i / eta - cos_theta_t) * n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The projected area of the microfacets must equal the macro surface
+    #[test]
+    fn ggx_normalized() {
+        let distrib = TrowbridgeReitz::new(0.3, 0.6);
+        let (n_theta, n_phi) = (512, 256);
+        let d_theta = PI / 2.0 / n_theta as f64;
+        let d_phi = 2.0 * PI / n_phi as f64;
+
+        let mut sum = 0.0;
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let wm = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                sum += distrib.d(&wm) * wm.z() * theta.sin() * d_theta * d_phi;
+            }
+        }
+
+        assert!((sum - 1.0).abs() < 1e-2, "sum = {sum}");
+    }
+}
@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Copy, Clone, Debug)]
+pub struct Progress {
+    // Of the whole render, between 0 and 1
+    pub fraction: f64,
+    pub elapsed: Duration,
+    // Extrapolated from the work done so far, None until there's some
+    pub eta: Option<Duration>,
+    pub samples_per_second: f64,
+}
+
+// Called from the rendering thread between rows or passes
+pub trait ProgressObserver: Send + Sync {
+    fn update(&self, progress: &Progress);
+}
+
+impl<F: Fn(&Progress) + Send + Sync> ProgressObserver for F {
+    fn update(&self, progress: &Progress) {
+        self(progress)
+    }
+}
+
+// Keeps a status line on stderr, the default observer
+pub struct StderrProgress;
+
+impl ProgressObserver for StderrProgress {
+    fn update(&self, progress: &Progress) {
+        let eta = progress.eta.map_or("-".to_string(), |eta| {
+            let secs = eta.as_secs();
+            format!("{}:{:02}", secs / 60, secs % 60)
+        });
+        eprint!(
+            "\rRendering {:5.1}%, eta {eta}, {:.2}M samples/s ",
+            100.0 * progress.fraction,
+            progress.samples_per_second * 1e-6
+        );
+        if progress.fraction >= 1.0 {
+            eprintln!();
+        }
+    }
+}
+
+// Shared flag a render checks between pixel tiles, cancelling keeps what was rendered so far
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RenderStatus {
+    Finished,
+    Cancelled,
+}
+
+// Turns the work done so far into progress reports
+pub(crate) struct ProgressTracker<'a> {
+    observer: &'a dyn ProgressObserver,
+    start: Instant,
+    samples: u64,
+}
+
+impl<'a> ProgressTracker<'a> {
+    pub fn new(observer: &'a dyn ProgressObserver) -> Self {
+        Self {
+            observer,
+            start: Instant::now(),
+            samples: 0,
+        }
+    }
+
+    pub fn report(&mut self, fraction: f64, new_samples: u64) {
+        self.samples += new_samples;
+
+        let fraction = fraction.clamp(0.0, 1.0);
+        let elapsed = self.start.elapsed();
+        let eta = (fraction > 0.0).then(|| elapsed.mul_f64((1.0 - fraction) / fraction));
+
+        self.observer.update(&Progress {
+            fraction,
+            elapsed,
+            eta,
+            samples_per_second: self.samples as f64 / elapsed.as_secs_f64().max(1e-9),
+        });
+    }
+}
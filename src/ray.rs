@@ -4,11 +4,26 @@ use crate::vec3::{Point3, Vec3};
 pub struct Ray {
     orig: Point3,
     dir: Vec3,
+    wavelength: Option<f64>,
 }
 
 impl Ray {
     pub fn new(orig: Point3, dir: Vec3) -> Self {
-        Self { orig, dir }
+        Self {
+            orig,
+            dir,
+            wavelength: None,
+        }
+    }
+
+    // Wavelength in nm picked by a dispersive material, kept for the rest of the path
+    pub fn with_wavelength(mut self, wavelength: f64) -> Self {
+        self.wavelength = Some(wavelength);
+        self
+    }
+
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength
     }
 
     pub fn origin(&self) -> Point3 {
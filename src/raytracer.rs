@@ -1,194 +1,1015 @@
 use rayon::prelude::*;
-use std::io;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::{Range, RangeInclusive};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 //use crate::color::{self, Color};
-use crate::ray::Ray;
-use crate::scene::{Hittable, Scene};
-use crate::utils::{self, rand_f64, Color};
-use crate::vec3::{Point3, Vec3};
-
-pub struct Camera {
-    center: Point3,
-    pixel00_loc: Point3,
-    pixel_du: Vec3,
-    pixel_dv: Vec3,
-    defocus_disk_u: Vec3,
-    defocus_disk_v: Vec3,
-    defocus_angle: f64,
-    pixel_samples_scale: f64,
+use crate::animation::CameraAnimation;
+use crate::aov::{Aov, PathSample};
+use crate::aperture::Aperture;
+use crate::camera::{Camera, CameraSettings, Projection, Stereo};
+use crate::checkpoint::{self, CheckpointHeader};
+use crate::denoise::{DenoiseInput, Denoiser};
+use crate::film::{Film, FilmTile};
+use crate::filter::Filter;
+use crate::image::Image;
+use crate::integrator::{Integrator, IntegratorKind, PathEvent};
+use crate::progress::{
+    CancelToken, ProgressObserver, ProgressTracker, RenderStatus, StderrProgress,
+};
+use crate::sampler::{self, SamplerKind};
+use crate::scene::Scene;
+use crate::stats::{self, CostMetric, RayCounters, RenderStats};
+use crate::tonemap::ToneMap;
+use crate::utils;
+use crate::vec3::Vec3;
+
+pub struct RenderConfig {
+    pub resolution: (u32, u32),
+    pub aspect_ratio: f64,
+    pub samples_per_pixel: u32,
+    pub max_depth: u32,
+    pub sampler: SamplerKind,
+    pub integrator: IntegratorKind,
+    pub seed: u64,
+    pub filter: Filter,
+    pub camera: CameraSettings,
+    pub projection: Projection,
+    pub stereo: Option<Stereo>,
+    pub aperture: Aperture,
+    // Strength of the cat's eye vignetting, 0 turns it off
+    pub cat_eye: f64,
+    // Overrides samples_per_pixel when set
+    pub adaptive: Option<AdaptiveSampling>,
+    // Refines the whole image pass by pass instead, adaptive sampling is ignored
+    pub progressive: Option<Progressive>,
+    // In stops
+    pub exposure: f64,
+    pub tone_map: ToneMap,
+    // Written next to the beauty image as <aov_output>.<name>.pfm
+    pub aovs: Vec<Aov>,
+    pub aov_output: PathBuf,
+    // Denoises the beauty image before it's written, rendering the albedo, normal and
+    // depth guides even when they aren't among the aovs
+    pub denoise: Option<Denoiser>,
+    // Only the pixels inside are rendered
+    pub crop: Option<Crop>,
+    // Prints the path of every sample of this pixel to stderr instead of rendering
+    pub debug_pixel: Option<(u32, u32)>,
+    // Written next to the aovs as <aov_output>.cost.ppm, brighter pixels took more work
+    pub heatmap: Option<CostMetric>,
 }
 
-impl Camera {
-    pub fn new(config: &RenderConfig) -> Self {
-        let (image_width, image_height) = config.resolution;
-        let samples_per_pixel = config.samples_per_pixel;
+// The beauty image of a render, tone mapping is left to `Raytracer::write_image`
+pub struct RenderOutput {
+    pub image: Image,
+    pub status: RenderStatus,
+}
 
-        let pixel_samples_scale = 1.0 / samples_per_pixel as f64;
+impl RenderOutput {
+    // Tracing a debug pixel renders no image
+    fn debug_pixel() -> Self {
+        Self {
+            image: Image::new(0, 0),
+            status: RenderStatus::Finished,
+        }
+    }
+}
 
-        let vfov = 20.0;
-        let defocus_angle = 0.6;
-        let focus_dist = 10.0;
+// Pixel window, either written on its own or into a full size image that's black around it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Crop {
+    pub x: Range<u32>,
+    pub y: Range<u32>,
+    pub full_size: bool,
+}
 
-        let lookfrom = Point3::new(13.0, 2.0, 3.0);
-        let lookat = Point3::new(0.0, 0.0, 0.0);
-        let vup = Vec3::new(0.0, 1.0, 0.0);
+// Pixels get at least min_samples, then more in doubling batches until the relative
+// standard error of their luminance drops below threshold or max_samples is reached
+#[derive(Copy, Clone, Debug)]
+pub struct AdaptiveSampling {
+    pub min_samples: u32,
+    pub max_samples: u32,
+    pub threshold: f64,
+}
+
+// Progressive rendering towards samples_per_pixel, stopping early on the time limit or
+// once every pixel's relative error is below the noise threshold. Converged pixels
+// are skipped and the image is rewritten to intermediate_output after every pass.
+// The film is saved to checkpoint between passes at most every checkpoint_interval
+// and when the render stops, resume continues from an existing checkpoint
+#[derive(Clone, Debug)]
+pub struct Progressive {
+    pub samples_per_pass: u32,
+    pub time_limit: Option<Duration>,
+    pub noise_threshold: Option<f64>,
+    pub intermediate_output: Option<PathBuf>,
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    pub resume: bool,
+}
+
+impl Default for Progressive {
+    fn default() -> Self {
+        Self {
+            samples_per_pass: 1,
+            time_limit: None,
+            noise_threshold: None,
+            intermediate_output: None,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(300),
+            resume: false,
+        }
+    }
+}
 
-        let theta = utils::deg_to_rad(vfov);
-        let h = f64::tan(theta / 2.0);
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            resolution: (400, 225),
+            aspect_ratio: 16.0 / 9.0,
+            samples_per_pixel: 100,
+            max_depth: 50,
+            sampler: SamplerKind::default(),
+            integrator: IntegratorKind::default(),
+            seed: 0,
+            filter: Filter::default(),
+            camera: CameraSettings::default(),
+            projection: Projection::default(),
+            stereo: None,
+            aperture: Aperture::default(),
+            cat_eye: 0.0,
+            adaptive: None,
+            progressive: None,
+            exposure: 0.0,
+            tone_map: ToneMap::default(),
+            aovs: Vec::new(),
+            aov_output: PathBuf::from("aov"),
+            denoise: None,
+            crop: None,
+            debug_pixel: None,
+            heatmap: None,
+        }
+    }
+}
 
-        let viewport_height = 2.0 * h * focus_dist;
-        let viewport_width = viewport_height * (image_width as f64 / image_height as f64);
+pub struct Raytracer {
+    scene: Scene,
+    camera: Camera,
+    integrator: Box<dyn Integrator>,
+    config: RenderConfig,
+    // The film's layers, the configured aovs followed by any missing denoiser guides
+    film_aovs: Vec<Aov>,
+    progress: Box<dyn ProgressObserver>,
+    cancel: CancelToken,
+    // Of the last render
+    stats: Mutex<RenderStats>,
+    // Per pixel in the heatmap's metric, empty without one
+    costs: Mutex<Vec<f64>>,
+}
 
-        let center = lookfrom;
+impl Raytracer {
+    pub fn new(config: RenderConfig, scene: Scene) -> io::Result<Self> {
+        let build_start = Instant::now();
+        let camera = Camera::new(&config)?;
+
+        let mut film_aovs = config.aovs.clone();
+        if config.denoise.is_some() {
+            for guide in [Aov::Albedo, Aov::Normal, Aov::Depth] {
+                if !film_aovs.contains(&guide) {
+                    film_aovs.push(guide);
+                }
+            }
+        }
 
-        let w = (lookfrom - lookat).unit();
-        let u = vup.cross(&w).unit();
-        let v = w.cross(&u).unit();
+        let (image_width, image_height) = config.resolution;
+        let cost_count = match config.heatmap {
+            Some(_) => (image_width * image_height) as usize,
+            None => 0,
+        };
 
-        let viewport_u = viewport_width * u;
-        let viewport_v = viewport_height * -v;
+        Ok(Self {
+            scene,
+            camera,
+            integrator: config.integrator.create(config.max_depth),
+            config,
+            film_aovs,
+            progress: Box::new(StderrProgress),
+            cancel: CancelToken::default(),
+            stats: Mutex::new(RenderStats {
+                build_time: build_start.elapsed(),
+                ..Default::default()
+            }),
+            costs: Mutex::new(vec![0.0; cost_count]),
+        })
+    }
 
-        let pixel_du = viewport_u / image_width as f64;
-        let pixel_dv = viewport_v / image_height as f64;
+    // The scene is built before the raytracer, so only the caller can time it
+    pub fn with_build_time(self, scene_build_time: Duration) -> Self {
+        self.stats.lock().unwrap().build_time += scene_build_time;
+        self
+    }
 
-        let viewport_upper_left = center - (focus_dist * w) - (viewport_u + viewport_v) / 2.0;
-        let pixel00_loc = viewport_upper_left + (pixel_du + pixel_dv) / 2.0;
+    pub fn with_progress(mut self, observer: impl ProgressObserver + 'static) -> Self {
+        self.progress = Box::new(observer);
+        self
+    }
 
-        let defocus_radius = focus_dist * f64::tan(utils::deg_to_rad(defocus_angle / 2.0));
-        let defocus_disk_u = u * defocus_radius;
-        let defocus_disk_v = v * defocus_radius;
+    // Cancelling stops the render after the pixels in flight, what was rendered is still
+    // returned. Every render starts uncancelled, so cancelling before it starts has no effect
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
 
-        Camera {
-            center,
-            pixel00_loc,
-            pixel_du,
-            pixel_dv,
-            pixel_samples_scale,
-            defocus_disk_u,
-            defocus_disk_v,
-            defocus_angle,
-        }
+    pub fn stats(&self) -> RenderStats {
+        *self.stats.lock().unwrap()
     }
 
-    fn get_ray(&self, i: u32, j: u32) -> Ray {
-        let [x, y, _] = Camera::sample_square().xyz();
+    // Writes the aovs and the heatmap, the image is left to the caller, see `write_image`.
+    // Fails when resuming from a checkpoint that can't be read or doesn't match the config
+    pub fn render_p(&self) -> io::Result<RenderOutput> {
+        if let Some((i, j)) = self.config.debug_pixel {
+            self.debug_pixel(i, j);
+            return Ok(RenderOutput::debug_pixel());
+        }
 
-        let x = x + i as f64;
-        let y = y + j as f64;
-        let pixel_sample = self.pixel00_loc + (x * self.pixel_du) + (y * self.pixel_dv);
-        let ray_origin = if self.defocus_angle <= 0.0 {
-            self.center
-        } else {
-            self.defocus_disk_sample()
+        self.cancel.reset();
+        self.reset_stats();
+        self.reset_costs();
+        let render_start = Instant::now();
+        let film = match &self.config.progressive {
+            Some(progressive) => self.render_progressive(progressive, true)?,
+            None => self.render_film_p(),
         };
-        let ray_direction = pixel_sample - ray_origin;
+        self.stats.lock().unwrap().render_time = render_start.elapsed();
 
-        Ray::new(ray_origin, ray_direction)
+        self.finish(&film)
     }
 
-    fn defocus_disk_sample(&self) -> Point3 {
-        let p = Vec3::rand_in_unit_disk();
-        self.center + (p.x() * self.defocus_disk_u) + (p.y() * self.defocus_disk_v)
-    }
+    pub fn render(&self) -> io::Result<RenderOutput> {
+        if let Some((i, j)) = self.config.debug_pixel {
+            self.debug_pixel(i, j);
+            return Ok(RenderOutput::debug_pixel());
+        }
+
+        self.cancel.reset();
+        self.reset_stats();
+        self.reset_costs();
+        let render_start = Instant::now();
+        let film = match &self.config.progressive {
+            Some(progressive) => self.render_progressive(progressive, false)?,
+            None => self.render_film(),
+        };
+        self.stats.lock().unwrap().render_time = render_start.elapsed();
 
-    // Anti-Aliasing
-    fn sample_square() -> Vec3 {
-        Vec3::new(rand_f64() - 0.5, rand_f64() - 0.5, 0.0)
+        self.finish(&film)
     }
-}
 
-pub struct RenderConfig {
-    pub resolution: (u32, u32),
-    pub aspect_ratio: f64,
-    pub samples_per_pixel: u32,
-    pub max_depth: u32,
-}
+    // Tone maps the image and writes it as a plain PPM
+    pub fn write_image(&self, image: &Image, out: &mut impl Write) -> io::Result<()> {
+        let (image_width, image_height) = (image.width(), image.height());
 
-pub struct Raytracer {
-    scene: Scene,
-    camera: Camera,
-    config: RenderConfig,
-}
+        writeln!(out, "P3\n{image_width} {image_height}\n255\n")?;
+        for j in 0..image_height {
+            for i in 0..image_width {
+                let color = image.get(i, j);
+                let color = self.config.tone_map.apply(color, self.config.exposure);
+                utils::write_color(out, color);
+            }
+        }
+        out.flush()
+    }
 
-impl Raytracer {
-    pub fn new(config: RenderConfig, scene: Scene) -> Self {
-        let camera = Camera::new(&config);
+    // Renders the frames of a camera animation to <output_prefix>0001.ppm and so on, AOVs are
+    // written next to each frame. Every frame gets its own seed so the noise doesn't freeze.
+    // Frames are rendered in one go, progressive and debug pixel settings are ignored
+    pub fn render_frames(
+        &mut self,
+        animation: &CameraAnimation,
+        frames: RangeInclusive<u32>,
+        output_prefix: &Path,
+    ) -> io::Result<RenderStatus> {
+        let (base_camera, base_seed) = (self.config.camera, self.config.seed);
+        let base_projection = self.config.projection.clone();
+        self.cancel.reset();
+        self.reset_stats();
+
+        let rendered = self.render_animated_frames(animation, frames, output_prefix);
+
+        self.config.camera = base_camera;
+        self.config.projection = base_projection;
+        self.config.seed = base_seed;
+        self.camera = Camera::new(&self.config)?;
+        rendered?;
+
+        eprint!("{}", self.stats());
+        Ok(self.status())
+    }
 
-        Self {
-            scene,
-            camera,
-            config,
+    // Leaves the last frame's camera and seed in the config, which the caller restores
+    fn render_animated_frames(
+        &mut self,
+        animation: &CameraAnimation,
+        frames: RangeInclusive<u32>,
+        output_prefix: &Path,
+    ) -> io::Result<()> {
+        let (base_camera, base_seed) = (self.config.camera, self.config.seed);
+
+        for frame in frames {
+            let key = animation.at(frame as f64);
+            self.config.camera = CameraSettings {
+                lookfrom: key.lookfrom,
+                lookat: key.lookat,
+                focus_dist: key.focus_dist,
+                ..base_camera
+            };
+            if let Projection::Perspective { .. } = self.config.projection {
+                self.config.projection = Projection::Perspective { vfov: key.vfov };
+            }
+            self.config.seed = base_seed.wrapping_add(frame as u64);
+            self.camera = Camera::new(&self.config)?;
+
+            eprintln!("Frame {frame}");
+            self.reset_costs();
+            let render_start = Instant::now();
+            let film = self.render_film_p();
+            self.stats.get_mut().unwrap().render_time += render_start.elapsed();
+            let output_start = Instant::now();
+
+            let mut base = output_prefix.as_os_str().to_owned();
+            base.push(format!("{frame:04}"));
+            let base = PathBuf::from(base);
+
+            let mut image_path = base.clone().into_os_string();
+            image_path.push(".ppm");
+            let mut out = BufWriter::new(File::create(image_path)?);
+            self.write_image(&self.resolve(&film), &mut out)?;
+            self.write_aovs(&film, &base)?;
+            self.write_heatmap(&base)?;
+            self.stats.get_mut().unwrap().output_time += output_start.elapsed();
+
+            if self.cancel.is_cancelled() {
+                break;
+            }
         }
+        Ok(())
     }
 
-    pub fn render_p(&self) {
+    fn new_film(&self) -> Film {
         let (image_width, image_height) = self.config.resolution;
-        let max_depth = self.config.max_depth;
+        Film::new(image_width, image_height, self.config.filter).with_layers(self.film_aovs.len())
+    }
 
-        println!("P3\n{image_width} {image_height}\n255\n");
+    fn render_film(&self) -> Film {
+        let (columns, rows) = self.region();
+        let mut film = self.new_film();
+        let mut tracker = ProgressTracker::new(self.progress.as_ref());
 
-        for j in 0..image_height {
-            eprint!("\rScanlines remaining: {} ", image_height - j);
+        'rows: for j in rows.clone() {
+            let mut samples = 0;
+            for i in columns.clone() {
+                if self.cancel.is_cancelled() {
+                    break 'rows;
+                }
+                let (tile, counters, time) = Self::measure(|| self.render_pixel(&film, i, j));
+                samples += tile.stats().count() as u64;
+                film.merge_tile(&tile);
+                self.add_cost(&tile, &counters, time);
+            }
+            tracker.report(Self::rows_done(&rows, j), samples);
+        }
 
-            let pixel_colors: Vec<_> = (0..image_width)
+        film
+    }
+
+    fn render_film_p(&self) -> Film {
+        let (columns, rows) = self.region();
+        let mut film = self.new_film();
+        let mut tracker = ProgressTracker::new(self.progress.as_ref());
+
+        for j in rows.clone() {
+            let tiles: Vec<_> = columns
+                .clone()
                 .into_par_iter()
-                .map(|i| {
-                    let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-                    for _ in 0..self.config.samples_per_pixel {
-                        pixel_color += self.ray_color(&self.camera.get_ray(i, j), max_depth);
+                .filter(|_| !self.cancel.is_cancelled())
+                .map(|i| Self::measure(|| self.render_pixel(&film, i, j)))
+                .collect();
+
+            // Merged in order so the result doesn't depend on scheduling
+            for (tile, counters, time) in &tiles {
+                film.merge_tile(tile);
+                self.add_cost(tile, counters, *time);
+            }
+
+            let samples = tiles
+                .iter()
+                .map(|(tile, _, _)| tile.stats().count() as u64)
+                .sum();
+            tracker.report(Self::rows_done(&rows, j), samples);
+            if self.cancel.is_cancelled() {
+                break;
+            }
+        }
+
+        film
+    }
+
+    // Passes over the whole image until samples_per_pixel, the time limit or the noise
+    // threshold is reached, the limits are checked between passes. The pixels of a row are
+    // rendered in parallel when asked to
+    fn render_progressive(&self, progressive: &Progressive, parallel: bool) -> io::Result<Film> {
+        let (columns, rows) = self.region();
+        let mut film = self.new_film();
+
+        let start_time = Instant::now();
+        let samples_per_pass = progressive.samples_per_pass.max(1);
+        let samples_per_pixel = self.config.samples_per_pixel;
+        let mut tracker = ProgressTracker::new(self.progress.as_ref());
+        let mut taken = 0;
+
+        if let Some(path) = progressive
+            .checkpoint
+            .as_ref()
+            .filter(|_| progressive.resume)
+        {
+            taken = self.resume_from(path, &mut film)?;
+        }
+        let mut last_checkpoint = Instant::now();
+
+        while taken < samples_per_pixel {
+            let samples = taken..(taken + samples_per_pass).min(samples_per_pixel);
+
+            for j in rows.clone() {
+                let pending = |&i: &u32| {
+                    !self.cancel.is_cancelled() && !self.is_converged(&film, progressive, i, j)
+                };
+                let render =
+                    |i| Self::measure(|| self.render_pixel_samples(&film, i, j, samples.clone()));
+                let tiles: Vec<_> = if parallel {
+                    columns
+                        .clone()
+                        .into_par_iter()
+                        .filter(pending)
+                        .map(render)
+                        .collect()
+                } else {
+                    columns.clone().filter(pending).map(render).collect()
+                };
+
+                for (tile, counters, time) in &tiles {
+                    film.merge_tile(tile);
+                    self.add_cost(tile, counters, *time);
+                }
+
+                // Towards the sample count, or the time limit when that's closer
+                let done = samples.start as f64 + samples.len() as f64 * Self::rows_done(&rows, j);
+                let fraction = progressive.time_limit.map_or(0.0, |limit| {
+                    start_time.elapsed().as_secs_f64() / limit.as_secs_f64()
+                });
+                let fraction = fraction.max(done / samples_per_pixel as f64);
+                tracker.report(fraction, (tiles.len() * samples.len()) as u64);
+
+                if self.cancel.is_cancelled() {
+                    break;
+                }
+            }
+
+            // The pass was cut short, the checkpoint keeps the last complete one so resuming
+            // doesn't take samples twice
+            if self.cancel.is_cancelled() {
+                break;
+            }
+            taken = samples.end;
+
+            if let Some(path) = &progressive.intermediate_output {
+                let written = File::create(path).and_then(|file| {
+                    self.write_image(&self.resolve(&film), &mut BufWriter::new(file))
+                });
+                if let Err(err) = written {
+                    eprintln!("\nCouldn't write {}: {err}", path.display());
+                }
+            }
+
+            let all_converged = rows.clone().all(|j| {
+                columns
+                    .clone()
+                    .all(|i| self.is_converged(&film, progressive, i, j))
+            });
+            let out_of_time = progressive
+                .time_limit
+                .is_some_and(|limit| start_time.elapsed() >= limit);
+            let stopping = all_converged || out_of_time || taken >= self.config.samples_per_pixel;
+
+            if let Some(path) = &progressive.checkpoint {
+                if stopping || last_checkpoint.elapsed() >= progressive.checkpoint_interval {
+                    let header = self.checkpoint_header(taken);
+                    if let Err(err) = checkpoint::save(path, &header, &film) {
+                        eprintln!("\nCouldn't save checkpoint {}: {err}", path.display());
                     }
+                    last_checkpoint = Instant::now();
+                }
+            }
 
-                    pixel_color
-                })
-                .collect();
+            if stopping {
+                break;
+            }
+        }
+
+        Ok(film)
+    }
+
+    fn checkpoint_header(&self, samples_taken: u32) -> CheckpointHeader {
+        CheckpointHeader {
+            seed: self.config.seed,
+            samples_per_pixel: self.config.samples_per_pixel,
+            samples_taken,
+            sampler: self.config.sampler,
+            filter: self.config.filter,
+            max_depth: self.config.max_depth,
+            integrator: self.config.integrator,
+            crop: self.config.crop.clone(),
+            view: checkpoint::view_fingerprint(&self.config),
+            noise_threshold: self
+                .config
+                .progressive
+                .as_ref()
+                .and_then(|p| p.noise_threshold),
+            aovs: self.film_aovs.clone(),
+        }
+    }
+
+    // Returns the number of samples already taken, starting over if there's no checkpoint yet
+    fn resume_from(&self, path: &Path, film: &mut Film) -> io::Result<u32> {
+        let context = |err: io::Error| {
+            io::Error::new(
+                err.kind(),
+                format!("Couldn't resume from {}: {err}", path.display()),
+            )
+        };
+        let header = match checkpoint::load(path, film) {
+            Ok(header) => header,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(context(err)),
+        };
+
+        // Anything else would make the remaining samples differ from the ones taken so far
+        if header != self.checkpoint_header(header.samples_taken) {
+            return Err(context(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "checkpoint was taken with a different render config",
+            )));
+        }
+
+        eprintln!("Resuming from {} samples per pixel", header.samples_taken);
+        Ok(header.samples_taken)
+    }
+
+    fn reset_stats(&self) {
+        let mut stats = self.stats.lock().unwrap();
+        *stats = RenderStats {
+            build_time: stats.build_time,
+            ..Default::default()
+        };
+    }
+
+    fn reset_costs(&self) {
+        self.costs.lock().unwrap().fill(0.0);
+    }
+
+    // Renders a pixel, returning what the rendering thread counted and how long it took
+    fn measure(render: impl FnOnce() -> FilmTile) -> (FilmTile, RayCounters, Duration) {
+        let start = Instant::now();
+        let tile = render();
+        (tile, stats::take(), start.elapsed())
+    }
+
+    fn add_cost(&self, tile: &FilmTile, counters: &RayCounters, time: Duration) {
+        self.stats.lock().unwrap().counters.merge(counters);
+
+        if let Some(metric) = self.config.heatmap {
+            let (i, j) = tile.center();
+            let cost = match metric {
+                CostMetric::Time => time.as_secs_f64(),
+                CostMetric::IntersectionTests => counters.intersection_tests as f64,
+            };
+            self.costs.lock().unwrap()[(j * self.config.resolution.0 + i) as usize] += cost;
+        }
+    }
+
+    // Resolves the image and writes the other outputs of a render
+    fn finish(&self, film: &Film) -> io::Result<RenderOutput> {
+        let output_start = Instant::now();
+        let image = self.resolve(film);
+        self.write_aovs(film, &self.config.aov_output)?;
+        self.write_heatmap(&self.config.aov_output)?;
+        self.stats.lock().unwrap().output_time = output_start.elapsed();
+
+        eprint!("{}", self.stats());
+        Ok(RenderOutput {
+            image,
+            status: self.status(),
+        })
+    }
+
+    fn status(&self) -> RenderStatus {
+        if self.cancel.is_cancelled() {
+            RenderStatus::Cancelled
+        } else {
+            RenderStatus::Finished
+        }
+    }
 
-            for pixel_color in pixel_colors {
-                utils::write_color(
-                    &mut io::stdout(),
-                    self.camera.pixel_samples_scale * pixel_color,
-                );
+    // Fraction of the rows done once row j is
+    fn rows_done(rows: &Range<u32>, j: u32) -> f64 {
+        (j + 1 - rows.start) as f64 / rows.len().max(1) as f64
+    }
+
+    // Columns and rows to render, the crop window clamped to the image
+    fn region(&self) -> (Range<u32>, Range<u32>) {
+        let (image_width, image_height) = self.config.resolution;
+        match &self.config.crop {
+            Some(crop) => (
+                crop.x.start.min(image_width)..crop.x.end.min(image_width),
+                crop.y.start.min(image_height)..crop.y.end.min(image_height),
+            ),
+            None => (0..image_width, 0..image_height),
+        }
+    }
+
+    // Puts an image of the region into the full image when the crop asks for it
+    fn frame_region(&self, image: Image) -> Image {
+        match &self.config.crop {
+            Some(crop) if crop.full_size => {
+                let (image_width, image_height) = self.config.resolution;
+                let (columns, rows) = self.region();
+                let mut full = Image::new(image_width, image_height);
+                full.paste(&image, (columns.start, rows.start));
+                full
             }
+            _ => image,
         }
     }
 
-    pub fn render(&self) {
+    fn is_converged(&self, film: &Film, progressive: &Progressive, i: u32, j: u32) -> bool {
+        progressive
+            .noise_threshold
+            .is_some_and(|threshold| film.stats(i, j).relative_error() < threshold)
+    }
+
+    // The beauty image of the rendered region, denoised when configured and framed the way
+    // the crop asks
+    fn resolve(&self, film: &Film) -> Image {
+        let (columns, rows) = self.region();
+        let crop = |image: Image| image.crop(columns.clone(), rows.clone());
+
+        let Some(denoiser) = &self.config.denoise else {
+            return self.frame_region(crop(film.image()));
+        };
+
+        let layer = |aov| {
+            let index = self.film_aovs.iter().position(|&a| a == aov).unwrap();
+            crop(film.layer_image(index))
+        };
+        let (albedo, normal, depth) = (layer(Aov::Albedo), layer(Aov::Normal), layer(Aov::Depth));
+
+        let variance: Vec<f64> = rows
+            .clone()
+            .flat_map(|j| columns.clone().map(move |i| film.stats(i, j)))
+            .map(|stats| stats.variance() / stats.count().max(1) as f64)
+            .collect();
+
+        self.frame_region(denoiser.denoise(&DenoiseInput {
+            color: &crop(film.image()),
+            albedo: Some(&albedo),
+            normal: Some(&normal),
+            depth: Some(&depth),
+            variance: Some(&variance),
+        }))
+    }
+
+    fn write_heatmap(&self, base: &Path) -> io::Result<()> {
+        let Some(metric) = self.config.heatmap else {
+            return Ok(());
+        };
+
         let (image_width, image_height) = self.config.resolution;
-        let max_depth = self.config.max_depth;
+        let (columns, rows) = self.region();
+        let costs = self.costs.lock().unwrap();
+        // Pixels outside the crop weren't rendered and would pull the percentile down
+        let rendered: Vec<f64> = rows
+            .clone()
+            .flat_map(|j| columns.clone().map(move |i| (j * image_width + i) as usize))
+            .map(|index| costs[index])
+            .collect();
+        let scale = stats::heatmap_scale(&rendered);
+        let image = stats::heatmap(&costs, scale, image_width, image_height);
+        let image = self.frame_region(image.crop(columns, rows));
+
+        let mut path = base.as_os_str().to_owned();
+        path.push(".cost.ppm");
+        image.write_ppm(path)?;
+
+        match metric {
+            CostMetric::Time => eprintln!("Heatmap: white is {:.3} ms or more", scale * 1e3),
+            CostMetric::IntersectionTests => eprintln!("Heatmap: white is {scale} tests or more"),
+        }
+        Ok(())
+    }
 
-        println!("P3\n{image_width} {image_height}\n255\n");
+    fn write_aovs(&self, film: &Film, base: &Path) -> io::Result<()> {
+        let (columns, rows) = self.region();
+        for (layer, aov) in self.config.aovs.iter().enumerate() {
+            let image = film.layer_image(layer).crop(columns.clone(), rows.clone());
+            let image = self.frame_region(image);
 
-        for j in 0..image_height {
-            eprint!("\rScanlines remaining: {} ", image_height - j);
+            let mut path = base.as_os_str().to_owned();
+            path.push(format!(".{}.pfm", aov.name()));
+            image.write_pfm(path)?;
+        }
+        Ok(())
+    }
 
-            for i in 0..image_width {
-                let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-                for _ in 0..self.config.samples_per_pixel {
-                    pixel_color += self.ray_color(&self.camera.get_ray(i, j), max_depth);
-                }
+    // Splats all samples of a pixel, adaptively when configured
+    fn render_pixel(&self, film: &Film, i: u32, j: u32) -> FilmTile {
+        let (min_samples, max_samples) = match self.config.adaptive {
+            Some(adaptive) => (adaptive.min_samples.max(2), adaptive.max_samples),
+            None => (self.config.samples_per_pixel, self.config.samples_per_pixel),
+        };
+        sampler::install(self.config.sampler.create(max_samples, self.config.seed));
+
+        let mut tile = film.tile_for_pixel(i, j);
+        let mut taken = 0;
+        let mut target = min_samples.min(max_samples);
+        loop {
+            self.add_samples(&mut tile, i, j, taken..target);
+            taken = target;
+
+            let converged = match self.config.adaptive {
+                Some(adaptive) => tile.stats().relative_error() < adaptive.threshold,
+                None => true,
+            };
+            if converged || taken >= max_samples {
+                break;
+            }
+            target = (2 * taken).min(max_samples);
+        }
+
+        sampler::uninstall();
+        tile
+    }
+
+    fn render_pixel_samples(&self, film: &Film, i: u32, j: u32, samples: Range<u32>) -> FilmTile {
+        let samples_per_pixel = self.config.samples_per_pixel;
+        sampler::install(
+            self.config
+                .sampler
+                .create(samples_per_pixel, self.config.seed),
+        );
+
+        let mut tile = film.tile_for_pixel(i, j);
+        self.add_samples(&mut tile, i, j, samples);
+
+        sampler::uninstall();
+        tile
+    }
+
+    // Every random number is drawn from the installed sampler, so sample s of a pixel
+    // is the same whichever pass or batch it's taken in
+    fn add_samples(&self, tile: &mut FilmTile, i: u32, j: u32, samples: Range<u32>) {
+        for s in samples {
+            sampler::start_pixel_sample((i, j), s);
+
+            let film_pos = Camera::sample_pixel(i, j);
+            let sample = match self.camera.get_ray(film_pos.0, film_pos.1) {
+                Some((ray, weight)) => self
+                    .integrator
+                    .trace(&self.scene, &ray, None)
+                    .weighted(weight),
+                None => PathSample::default(),
+            };
+            let aovs: Vec<_> = self.film_aovs.iter().map(|&aov| sample.aov(aov)).collect();
+            tile.add_sample(film_pos, sample.color, &aovs);
+        }
+    }
 
-                utils::write_color(
-                    &mut io::stdout(),
-                    self.camera.pixel_samples_scale * pixel_color,
-                );
+    // Traces the samples of one pixel like the render does and prints what every path hit
+    fn debug_pixel(&self, i: u32, j: u32) {
+        let samples = match self.config.adaptive {
+            Some(adaptive) => adaptive.max_samples,
+            None => self.config.samples_per_pixel,
+        };
+        sampler::install(self.config.sampler.create(samples, self.config.seed));
+
+        let fmt = |v: Vec3| format!("({:.4}, {:.4}, {:.4})", v.x(), v.y(), v.z());
+        for s in 0..samples {
+            sampler::start_pixel_sample((i, j), s);
+
+            let film_pos = Camera::sample_pixel(i, j);
+            eprintln!("Sample {s} at ({:.3}, {:.3})", film_pos.0, film_pos.1);
+            let Some((ray, weight)) = self.camera.get_ray(film_pos.0, film_pos.1) else {
+                eprintln!("  Blocked by the camera");
+                continue;
+            };
+            eprintln!("  Ray {} -> {}", fmt(ray.origin()), fmt(ray.direction()));
+
+            let mut path = Vec::new();
+            let sample = self
+                .integrator
+                .trace(&self.scene, &ray, Some(&mut path))
+                .weighted(weight);
+            for (bounce, event) in path.iter().enumerate() {
+                match event {
+                    PathEvent::Scattered {
+                        hit,
+                        object_id,
+                        material_id,
+                        attenuation,
+                        scattered,
+                    } => eprintln!(
+                        "  {bounce}: object {object_id} material {material_id} at {}, attenuation {}, scattered to {}",
+                        fmt(*hit),
+                        fmt(*attenuation),
+                        fmt(*scattered)
+                    ),
+                    PathEvent::Absorbed {
+                        hit,
+                        object_id,
+                        material_id,
+                    } => eprintln!(
+                        "  {bounce}: object {object_id} material {material_id} at {}, absorbed",
+                        fmt(*hit)
+                    ),
+                    PathEvent::Escaped { radiance } => {
+                        eprintln!("  {bounce}: escaped with {}", fmt(*radiance))
+                    }
+                }
             }
+            eprintln!("  Radiance {} with weight {weight:.4}", fmt(sample.color));
         }
+
+        sampler::uninstall();
+        stats::take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::progress::Progress;
+    use crate::utils::Color;
+    use crate::vec3::Point3;
+
+    fn raytracer(scene: Scene) -> Raytracer {
+        let config = RenderConfig {
+            resolution: (9, 9),
+            aspect_ratio: 1.0,
+            adaptive: Some(AdaptiveSampling {
+                min_samples: 4,
+                max_samples: 64,
+                threshold: 1e-3,
+            }),
+            ..Default::default()
+        };
+        Raytracer::new(config, scene).unwrap()
     }
 
-    fn ray_color(&self, r: &Ray, depth: u32) -> Color {
-        if depth == 0 {
-            return Color::new(0.0, 0.0, 0.0);
+    fn center_pixel_samples(rt: &Raytracer) -> u32 {
+        rt.render_pixel(&rt.new_film(), 4, 4).stats().count()
+    }
+
+    #[test]
+    fn adaptive_sampling_stops_once_converged() {
+        let grey = || Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+
+        // Every sample of the center pixel is black
+        let mut flat = Scene::new();
+        let black = Arc::new(Lambertian::new(Color::new(0.0, 0.0, 0.0)));
+        flat.add_sphere(Point3::default(), 5.0, black);
+        assert_eq!(center_pixel_samples(&raytracer(flat)), 4);
+
+        // Diffuse bounces between the sphere, the ground and the sky
+        let mut mixed = Scene::new();
+        mixed.add_sphere(Point3::default(), 1.0, grey());
+        mixed.add_sphere(Point3::new(0.0, -1001.0, 0.0), 1000.0, grey());
+        assert_eq!(center_pixel_samples(&raytracer(mixed)), 64);
+    }
+
+    #[test]
+    fn cancelling_keeps_the_rendered_rows() {
+        for parallel in [false, true] {
+            let config = RenderConfig {
+                resolution: (9, 9),
+                aspect_ratio: 1.0,
+                samples_per_pixel: 4,
+                ..Default::default()
+            };
+            let rt = Raytracer::new(config, Scene::new()).unwrap();
+            let token = rt.cancel_token();
+            let rt = rt.with_progress(move |_: &Progress| token.cancel());
+
+            let output = if parallel { rt.render_p() } else { rt.render() }.unwrap();
+            assert_eq!(output.status, RenderStatus::Cancelled);
+
+            // The sky is never black, rows that weren't rendered are
+            for i in 0..9 {
+                assert!(output.image.get(i, 0).len() > 0.0);
+                for j in 1..9 {
+                    assert_eq!(output.image.get(i, j).len(), 0.0);
+                }
+            }
         }
+    }
+
+    // A token cancelled after one render doesn't stop the next
+    #[test]
+    fn renders_start_uncancelled() {
+        let config = RenderConfig {
+            resolution: (3, 3),
+            aspect_ratio: 1.0,
+            samples_per_pixel: 1,
+            ..Default::default()
+        };
+        let rt = Raytracer::new(config, Scene::new()).unwrap();
+        rt.cancel_token().cancel();
+
+        assert_eq!(rt.render_p().unwrap().status, RenderStatus::Finished);
+        assert_eq!(rt.render().unwrap().status, RenderStatus::Finished);
+    }
+
+    #[test]
+    fn serial_render_is_progressive_too() {
+        let path = std::env::temp_dir().join(format!("rayrs-{}-pass.ppm", std::process::id()));
+        let mut scene = Scene::new();
+        let grey = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        scene.add_sphere(Point3::default(), 1.0, grey);
+
+        let config = RenderConfig {
+            resolution: (9, 9),
+            aspect_ratio: 1.0,
+            samples_per_pixel: 4,
+            progressive: Some(Progressive {
+                samples_per_pass: 2,
+                intermediate_output: Some(path.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let rt = Raytracer::new(config, scene).unwrap();
+
+        // Each writes the image after every pass
+        let serial = rt.render().unwrap().image;
+        fs::remove_file(&path).unwrap();
+        let parallel = rt.render_p().unwrap().image;
+        fs::remove_file(&path).unwrap();
 
-        if let Some(rec) = self.scene.hit(r, 0.001, f64::INFINITY) {
-            if let Some(scatter) = rec.mat.scatter(r, &rec) {
-                return scatter.attenuation * self.ray_color(&scatter.scattered, depth - 1);
+        for j in 0..9 {
+            for i in 0..9 {
+                assert_eq!(serial.get(i, j).xyz(), parallel.get(i, j).xyz());
             }
-            //let ray = Ray::new(rec.p, rec.normal + Vec3::rand_unit_vec());
-            return Color::new(0.0, 0.0, 0.0);
         }
+    }
+
+    #[test]
+    fn resume_rejects_a_different_camera() {
+        let path =
+            std::env::temp_dir().join(format!("rayrs-{}-resume.checkpoint", std::process::id()));
+        let config = |lookfrom, resume| RenderConfig {
+            resolution: (9, 9),
+            aspect_ratio: 1.0,
+            samples_per_pixel: 2,
+            camera: CameraSettings {
+                lookfrom,
+                ..Default::default()
+            },
+            progressive: Some(Progressive {
+                checkpoint: Some(path.clone()),
+                resume,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let lookfrom = Point3::new(13.0, 2.0, 3.0);
 
-        let unit_direction = r.direction().unit();
-        let a: f64 = (unit_direction.y() + 1.0) / 2.0;
-        (1.0 - a) * Color::new(1.0, 1.0, 1.0) + a * Color::new(0.5, 0.7, 1.0)
+        Raytracer::new(config(lookfrom, false), Scene::new())
+            .unwrap()
+            .render_p()
+            .unwrap();
+        let same = Raytracer::new(config(lookfrom, true), Scene::new())
+            .unwrap()
+            .render_p();
+        let moved = Raytracer::new(config(Point3::new(0.0, 2.0, 13.0), true), Scene::new())
+            .unwrap()
+            .render_p();
+        fs::remove_file(&path).unwrap();
+
+        assert!(same.is_ok());
+        assert_eq!(moved.err().unwrap().kind(), io::ErrorKind::InvalidData);
     }
 }
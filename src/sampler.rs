@@ -0,0 +1,428 @@
+use std::cell::RefCell;
+
+use rand::Rng;
+
+// Sample values for one pixel sample at a time, dimension by dimension.
+// Every `get_*` call consumes the next dimension(s) of the current sample
+pub trait Sampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), sample_index: u32);
+
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum SamplerKind {
+    #[default]
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn create(self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Box<dyn Sampler>>> = const { RefCell::new(None) };
+}
+
+// Makes `sampler` the source of every random number drawn on this thread
+pub fn install(sampler: Box<dyn Sampler>) {
+    CURRENT.with(|current| *current.borrow_mut() = Some(sampler));
+}
+
+pub fn uninstall() -> Option<Box<dyn Sampler>> {
+    CURRENT.with(|current| current.borrow_mut().take())
+}
+
+pub fn start_pixel_sample(pixel: (u32, u32), sample_index: u32) {
+    CURRENT.with(|current| {
+        if let Some(sampler) = current.borrow_mut().as_mut() {
+            sampler.start_pixel_sample(pixel, sample_index);
+        }
+    });
+}
+
+// Falls back to independent uniform randoms when no sampler is installed
+pub fn get_1d() -> f64 {
+    CURRENT.with(|current| match current.borrow_mut().as_mut() {
+        Some(sampler) => sampler.get_1d(),
+        None => rand::thread_rng().gen(),
+    })
+}
+
+pub fn get_2d() -> (f64, f64) {
+    CURRENT.with(|current| match current.borrow_mut().as_mut() {
+        Some(sampler) => sampler.get_2d(),
+        None => {
+            let mut rng = rand::thread_rng();
+            (rng.gen(), rng.gen())
+        }
+    })
+}
+
+pub struct IndependentSampler {
+    seed: u64,
+    state: u64,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, state: 0 }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.state = hash(&[
+            pixel.0 as u64,
+            pixel.1 as u64,
+            sample_index as u64,
+            self.seed,
+        ]);
+    }
+
+    // splitmix64
+    fn get_1d(&mut self) -> f64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        to_unit_f64(mix_bits(self.state))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Jittered strata per dimension, randomly matched up across dimensions
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel_hash: u64,
+    sample_index: u32,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed,
+            pixel_hash: 0,
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn jitter(&self, offset: u64) -> f64 {
+        let h = hash(&[
+            self.pixel_hash,
+            self.dimension + offset,
+            self.sample_index as u64,
+        ]);
+        to_unit_f64(h)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.pixel_hash = hash(&[pixel.0 as u64, pixel.1 as u64, self.seed]);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let n = self.samples_per_pixel;
+        let perm_seed = hash(&[self.pixel_hash, self.dimension]) as u32;
+        let stratum = permutation_element(self.sample_index % n, n, perm_seed);
+
+        let value = (stratum as f64 + self.jitter(0)) / n as f64;
+        self.dimension += 1;
+        value
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        // Closest grid to square with at least one stratum per sample
+        let nx = (self.samples_per_pixel as f64).sqrt().ceil() as u32;
+        let ny = self.samples_per_pixel.div_ceil(nx);
+
+        let perm_seed = hash(&[self.pixel_hash, self.dimension]) as u32;
+        let stratum = permutation_element(self.sample_index % (nx * ny), nx * ny, perm_seed);
+
+        let x = ((stratum % nx) as f64 + self.jitter(0)) / nx as f64;
+        let y = ((stratum / nx) as f64 + self.jitter(1)) / ny as f64;
+        self.dimension += 2;
+        (x, y)
+    }
+}
+
+// Owen scrambled Halton sequence, decorrelated per pixel
+pub struct HaltonSampler {
+    seed: u64,
+    pixel_hash: u64,
+    sample_index: u64,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    const PRIMES: [u32; 64] = [
+        2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89,
+        97, 101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181,
+        191, 193, 197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281,
+        283, 293, 307, 311,
+    ];
+
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel_hash: 0,
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.pixel_hash = hash(&[pixel.0 as u64, pixel.1 as u64, self.seed]);
+        self.sample_index = sample_index as u64;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dim_hash = hash(&[self.pixel_hash, self.dimension as u64]);
+
+        // Past the prime table there's no low discrepancy left to gain
+        let value = match Self::PRIMES.get(self.dimension) {
+            Some(&base) => owen_scrambled_radical_inverse(base, self.sample_index, dim_hash),
+            None => to_unit_f64(hash(&[dim_hash, self.sample_index])),
+        };
+
+        self.dimension += 1;
+        value
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Owen scrambled Sobol (0, 2)-sequence padded across dimensions with per dimension
+// nested shuffles of the sample index, Burley 2020, "Practical Hash-based Owen Scrambling".
+// Any power of two prefix of the samples stays stratified, whatever the final count
+pub struct SobolSampler {
+    seed: u64,
+    pixel_hash: u64,
+    sample_index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel_hash: 0,
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn shuffled_index(&self, dim_hash: u64) -> u32 {
+        fast_owen_scramble(self.sample_index, dim_hash as u32)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.pixel_hash = hash(&[pixel.0 as u64, pixel.1 as u64, self.seed]);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dim_hash = hash(&[self.pixel_hash, self.dimension]);
+        let index = self.shuffled_index(dim_hash);
+
+        self.dimension += 1;
+        to_unit_f32(fast_owen_scramble(sobol_0(index), (dim_hash >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dim_hash = hash(&[self.pixel_hash, self.dimension]);
+        let index = self.shuffled_index(dim_hash);
+        let scramble = mix_bits(dim_hash);
+
+        self.dimension += 2;
+        (
+            to_unit_f32(fast_owen_scramble(sobol_0(index), scramble as u32)),
+            to_unit_f32(fast_owen_scramble(sobol_1(index), (scramble >> 32) as u32)),
+        )
+    }
+}
+
+// First two Sobol dimensions, the van der Corput sequence and its (0, 2) companion
+fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+fn sobol_1(mut index: u32) -> u32 {
+    let mut v = 1u32 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+// Laine and Karras style hash based Owen scrambling, as in pbrt's FastOwenScrambler
+fn fast_owen_scramble(v: u32, seed: u32) -> u32 {
+    let mut v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x05526c56);
+    v ^= v.wrapping_mul(0x53a22864);
+    v.reverse_bits()
+}
+
+fn owen_scrambled_radical_inverse(base: u32, mut a: u64, seed: u64) -> f64 {
+    let base = base as u64;
+    let inv_base = 1.0 / base as f64;
+
+    let mut inv_base_m = 1.0;
+    let mut reversed_digits: u64 = 0;
+    let mut value = 0.0;
+
+    // Digits past the index are scrambled too, until the precision runs out
+    while inv_base_m > 1e-15 {
+        let digit = a % base;
+        a /= base;
+
+        let digit_hash = mix_bits(seed ^ reversed_digits);
+        let digit = permutation_element(digit as u32, base as u32, digit_hash as u32) as u64;
+        reversed_digits = reversed_digits.wrapping_mul(base).wrapping_add(digit);
+
+        inv_base_m *= inv_base;
+        value += digit as f64 * inv_base_m;
+    }
+
+    value.min(ONE_MINUS_EPSILON)
+}
+
+// Element i of a random permutation of 0..n selected by seed, Kensler 2013
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | (seed >> 27));
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < n {
+            break;
+        }
+    }
+
+    (i.wrapping_add(seed)) % n
+}
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+pub(crate) fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0x9e3779b97f4a7c15, |h, &v| mix_bits(h ^ mix_bits(v)))
+}
+
+fn to_unit_f64(v: u64) -> f64 {
+    (v >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn to_unit_f32(v: u32) -> f64 {
+    (v as f64 / (1u64 << 32) as f64).min(ONE_MINUS_EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples_2d(kind: SamplerKind, n: u32, dimension: u32) -> Vec<(f64, f64)> {
+        let mut sampler = kind.create(n, 7);
+        (0..n)
+            .map(|i| {
+                sampler.start_pixel_sample((3, 5), i);
+                for _ in 0..dimension {
+                    sampler.get_2d();
+                }
+                sampler.get_2d()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn one_sample_per_stratum() {
+        for kind in [SamplerKind::Stratified, SamplerKind::Sobol] {
+            for dimension in [0, 1, 5] {
+                let mut strata = [false; 16];
+                for (x, y) in samples_2d(kind, 16, dimension) {
+                    assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+                    strata[(y * 4.0) as usize * 4 + (x * 4.0) as usize] = true;
+                }
+                assert!(strata.iter().all(|&s| s), "{kind:?} dimension {dimension}");
+            }
+        }
+    }
+
+    #[test]
+    fn halton_in_range() {
+        let mut sampler = SamplerKind::Halton.create(64, 1);
+        for i in 0..64 {
+            sampler.start_pixel_sample((0, 0), i);
+            for _ in 0..80 {
+                assert!((0.0..1.0).contains(&sampler.get_1d()));
+            }
+        }
+    }
+}
@@ -1,9 +1,12 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::material::Material;
+use crate::microfacet::Frame;
 use crate::ray::Ray;
 use crate::sphere::Sphere;
-use crate::utils::Color;
+use crate::stats;
+use crate::triangle::Triangle;
 use crate::vec3::{Point3, Vec3};
 
 pub trait Hittable: Send + Sync {
@@ -12,21 +15,87 @@ pub trait Hittable: Send + Sync {
 
 #[derive(Default)]
 pub struct Scene {
-    objects: Vec<Box<dyn Hittable>>,
+    // Tagged with their object id, the triangles of a mesh share one
+    objects: Vec<(u32, Box<dyn Hittable>)>,
+    object_count: u32,
+    // Keyed by the material's address, only materials passed to the add_* helpers get one
+    material_ids: HashMap<usize, u32>,
 }
 
 impl Scene {
+    const MASK_EPSILON: f64 = 1e-6;
+
     pub fn new() -> Scene {
         Default::default()
     }
 
     pub fn add(&mut self, object: Box<dyn Hittable>) {
-        self.objects.push(object);
+        let id = self.next_object_id();
+        self.objects.push((id, object));
     }
 
     pub fn add_sphere(&mut self, center: Point3, radius: f64, mat: Arc<dyn Material>) {
+        self.register_material(&mat);
         self.add(Box::new(Sphere::new(center, radius, mat)));
     }
+
+    pub fn add_triangle(&mut self, v0: Point3, v1: Point3, v2: Point3, mat: Arc<dyn Material>) {
+        self.register_material(&mat);
+        self.add(Box::new(Triangle::new([v0, v1, v2], mat)));
+    }
+
+    // Parallelogram with corner q and edges u and v, as two uv mapped triangles
+    pub fn add_quad(&mut self, q: Point3, u: Vec3, v: Vec3, mat: Arc<dyn Material>) {
+        let positions = [q, q + u, q + u + v, q + v];
+        let uvs = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        self.add_mesh(&positions, Some(&uvs), &[[0, 1, 2], [0, 2, 3]], mat);
+    }
+
+    // Indexed triangle mesh, uvs are per vertex and optional
+    pub fn add_mesh(
+        &mut self,
+        positions: &[Point3],
+        uvs: Option<&[(f64, f64)]>,
+        indices: &[[usize; 3]],
+        mat: Arc<dyn Material>,
+    ) {
+        self.register_material(&mat);
+        let id = self.next_object_id();
+
+        for &[i0, i1, i2] in indices {
+            let vertices = [positions[i0], positions[i1], positions[i2]];
+            let triangle = match uvs {
+                Some(uvs) => Triangle::with_uvs(vertices, [uvs[i0], uvs[i1], uvs[i2]], mat.clone()),
+                None => Triangle::new(vertices, mat.clone()),
+            };
+            self.objects.push((id, Box::new(triangle)));
+        }
+    }
+
+    // Ids start at 1, 0 is left for the background
+    fn next_object_id(&mut self) -> u32 {
+        self.object_count += 1;
+        self.object_count
+    }
+
+    fn register_material(&mut self, mat: &Arc<dyn Material>) {
+        let next_id = self.material_ids.len() as u32 + 1;
+        self.material_ids
+            .entry(Self::material_key(mat))
+            .or_insert(next_id);
+    }
+
+    // 0 for materials the scene doesn't know about
+    pub fn material_id(&self, mat: &Arc<dyn Material>) -> u32 {
+        self.material_ids
+            .get(&Self::material_key(mat))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn material_key(mat: &Arc<dyn Material>) -> usize {
+        Arc::as_ptr(mat) as *const () as usize
+    }
 }
 
 impl Hittable for Scene {
@@ -34,10 +103,29 @@ impl Hittable for Scene {
         let mut rec = None;
         let mut closest_so_far = ray_tmax;
 
-        for object in &self.objects {
-            if let Some(temp_rec) = object.hit(r, ray_tmin, closest_so_far) {
+        for (id, object) in &self.objects {
+            // Keep looking past masked out hits, they might hide another surface of the object
+            let mut t_min = ray_tmin;
+            loop {
+                stats::record(|counters| counters.intersection_tests += 1);
+                let Some(mut temp_rec) = object.hit(r, t_min, closest_so_far) else {
+                    break;
+                };
+
+                let material_id = self.material_id(&temp_rec.mat);
+                if let Some(mat) = temp_rec.mat.resolve(&temp_rec) {
+                    temp_rec.mat = mat;
+                }
+                if temp_rec.mat.is_masked(&temp_rec) {
+                    t_min = temp_rec.t + Self::MASK_EPSILON;
+                    continue;
+                }
+
                 closest_so_far = temp_rec.t;
-                rec = Some(temp_rec)
+                temp_rec.object_id = *id;
+                temp_rec.material_id = material_id;
+                rec = Some(temp_rec);
+                break;
             }
         }
 
@@ -45,12 +133,23 @@ impl Hittable for Scene {
     }
 }
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
+    // Shading frame, the normal faces the incoming ray
     pub normal: Vec3,
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
     pub mat: Arc<dyn Material>,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
     pub front_face: bool,
+    // Weights of the second and third vertex on triangles, 0 on other shapes
+    pub barycentric: (f64, f64),
+    // Set by the scene, the material id is the one of the material before it was resolved
+    pub object_id: u32,
+    pub material_id: u32,
 }
 
 impl HitRecord {
@@ -63,4 +162,20 @@ impl HitRecord {
             -out_normal
         };
     }
+
+    // Call after set_face_normal, tangent is projected onto the surface
+    pub fn set_tangent(&mut self, tangent: Vec3) {
+        let projected = tangent - self.normal.dot(&tangent) * self.normal;
+
+        self.tangent = if projected.len_sq() > 1e-12 {
+            projected.unit()
+        } else {
+            Frame::from_normal(self.normal).from_local(Vec3::new(1.0, 0.0, 0.0))
+        };
+        self.bitangent = self.normal.cross(&self.tangent);
+    }
+
+    pub fn shading_frame(&self) -> Frame {
+        Frame::new(self.tangent, self.bitangent, self.normal)
+    }
 }
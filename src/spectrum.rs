@@ -0,0 +1,92 @@
+use std::sync::OnceLock;
+
+use crate::utils::Color;
+
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+
+// Wavelength dependent index of refraction, wavelengths in nm
+#[derive(Copy, Clone)]
+pub enum Dispersion {
+    // n = a + b / lambda^2, lambda in micrometers
+    Cauchy { a: f64, b: f64 },
+    // n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i), lambda in micrometers
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    pub fn fused_silica() -> Self {
+        Dispersion::Sellmeier {
+            b: [0.6961663, 0.4079426, 0.8974794],
+            c: [
+                0.0684043f64.powi(2),
+                0.1162414f64.powi(2),
+                9.896161f64.powi(2),
+            ],
+        }
+    }
+
+    pub fn ior(&self, wavelength: f64) -> f64 {
+        let l = wavelength / 1000.0;
+        let l2 = l * l;
+
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f64 = b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
+
+// Piecewise gaussian fit of the CIE 1931 matching functions
+// Wyman et al. 2013, "Simple Analytic Approximations to the CIE XYZ Color Matching Functions"
+fn cie_xyz(wavelength: f64) -> [f64; 3] {
+    let g = |mu: f64, sigma1: f64, sigma2: f64| {
+        let sigma = if wavelength < mu { sigma1 } else { sigma2 };
+        let t = (wavelength - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+
+    let x =
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+
+    [x, y, z]
+}
+
+fn wavelength_to_linear_srgb(wavelength: f64) -> Color {
+    let [x, y, z] = cie_xyz(wavelength);
+
+    Color::new(
+        (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0),
+    )
+}
+
+// Weight of a wavelength sampled uniformly in [LAMBDA_MIN, LAMBDA_MAX], averages to white
+pub fn wavelength_weight(wavelength: f64) -> Color {
+    static MEAN: OnceLock<Color> = OnceLock::new();
+
+    let mean = MEAN.get_or_init(|| {
+        const N: u32 = 1000;
+        let mut sum = Color::default();
+        for i in 0..N {
+            let t = (i as f64 + 0.5) / N as f64;
+            sum += wavelength_to_linear_srgb(LAMBDA_MIN + t * (LAMBDA_MAX - LAMBDA_MIN));
+        }
+        sum / N as f64
+    });
+
+    wavelength_to_linear_srgb(wavelength) / *mean
+}
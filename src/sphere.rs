@@ -1,9 +1,11 @@
+use std::f64::consts::PI;
 use std::sync::Arc;
 
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::scene::{HitRecord, Hittable};
-use crate::vec3::Point3;
+use crate::stats;
+use crate::vec3::{Point3, Vec3};
 
 pub struct Sphere {
     center: Point3,
@@ -19,10 +21,20 @@ impl Sphere {
             mat,
         }
     }
+
+    // p is a point on the unit sphere, u and v are in [0, 1]
+    fn get_sphere_uv(p: &Point3) -> (f64, f64) {
+        let theta = f64::acos(-p.y());
+        let phi = f64::atan2(-p.z(), p.x()) + PI;
+
+        (phi / (2.0 * PI), theta / PI)
+    }
 }
 
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        stats::record(|counters| counters.sphere_tests += 1);
+
         let ray_dir = r.direction();
         let oc = self.center - r.origin();
 
@@ -53,11 +65,22 @@ impl Hittable for Sphere {
             p: r.at(root),
             mat: self.mat.clone(),
             normal: Default::default(),
+            tangent: Default::default(),
+            bitangent: Default::default(),
+            u: Default::default(),
+            v: Default::default(),
             front_face: Default::default(),
+            barycentric: Default::default(),
+            object_id: Default::default(),
+            material_id: Default::default(),
         };
 
         let out_normal = (rec.p - self.center) / self.radius;
         rec.set_face_normal(r, out_normal);
+        (rec.u, rec.v) = Self::get_sphere_uv(&out_normal);
+
+        // dp/du points along increasing phi, degenerate at the poles
+        rec.set_tangent(Vec3::new(out_normal.z(), 0.0, -out_normal.x()));
 
         Some(rec)
     }
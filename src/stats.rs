@@ -0,0 +1,170 @@
+use std::cell::Cell;
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+use crate::image::Image;
+use crate::utils::Color;
+
+// Counted per thread while tracing and collected after every pixel. Materials don't sample
+// lights, so shadow rays are only traced for ambient occlusion
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RayCounters {
+    pub primary_rays: u64,
+    pub secondary_rays: u64,
+    pub shadow_rays: u64,
+    // Every hit test against an object of the scene, spheres and triangles included
+    pub intersection_tests: u64,
+    pub sphere_tests: u64,
+    pub triangle_tests: u64,
+}
+
+impl RayCounters {
+    const ZERO: RayCounters = RayCounters {
+        primary_rays: 0,
+        secondary_rays: 0,
+        shadow_rays: 0,
+        intersection_tests: 0,
+        sphere_tests: 0,
+        triangle_tests: 0,
+    };
+
+    pub fn merge(&mut self, other: &RayCounters) {
+        self.primary_rays += other.primary_rays;
+        self.secondary_rays += other.secondary_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.intersection_tests += other.intersection_tests;
+        self.sphere_tests += other.sphere_tests;
+        self.triangle_tests += other.triangle_tests;
+    }
+
+    pub fn rays(&self) -> u64 {
+        self.primary_rays + self.secondary_rays + self.shadow_rays
+    }
+
+    // Tests against user defined hittables
+    pub fn other_tests(&self) -> u64 {
+        self.intersection_tests - self.sphere_tests - self.triangle_tests
+    }
+
+    // Segments per camera ray
+    pub fn average_path_length(&self) -> f64 {
+        if self.primary_rays == 0 {
+            return 0.0;
+        }
+        (self.primary_rays + self.secondary_rays) as f64 / self.primary_rays as f64
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RenderStats {
+    pub counters: RayCounters,
+    // Setting up the camera and the renderer, plus building the scene when the caller
+    // reports it with `Raytracer::with_build_time`
+    pub build_time: Duration,
+    pub render_time: Duration,
+    // Denoising and writing the image and aovs
+    pub output_time: Duration,
+}
+
+impl RenderStats {
+    pub fn mrays_per_second(&self) -> f64 {
+        self.counters.rays() as f64 * 1e-6 / self.render_time.as_secs_f64().max(1e-9)
+    }
+}
+
+impl Display for RenderStats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let c = &self.counters;
+        writeln!(f, "Render statistics")?;
+        writeln!(
+            f,
+            "  Rays: {} primary, {} secondary, {} shadow",
+            c.primary_rays, c.secondary_rays, c.shadow_rays
+        )?;
+        writeln!(
+            f,
+            "  Intersection tests: {} spheres, {} triangles, {} other",
+            c.sphere_tests,
+            c.triangle_tests,
+            c.other_tests()
+        )?;
+        writeln!(f, "  Average path length: {:.3}", c.average_path_length())?;
+        writeln!(
+            f,
+            "  Time: build {:.3}s, render {:.3}s, output {:.3}s",
+            self.build_time.as_secs_f64(),
+            self.render_time.as_secs_f64(),
+            self.output_time.as_secs_f64()
+        )?;
+        writeln!(f, "  {:.2} Mrays/s", self.mrays_per_second())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CostMetric {
+    // Wall clock time spent on the pixel's samples
+    Time,
+    IntersectionTests,
+}
+
+// Cost shown as white, the 99th percentile so a few outliers don't darken everything else
+pub fn heatmap_scale(costs: &[f64]) -> f64 {
+    let mut sorted = costs.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    sorted
+        .get((sorted.len() as f64 * 0.99) as usize)
+        .or(sorted.last())
+        .copied()
+        .unwrap_or(0.0)
+}
+
+// False color image of per pixel costs, from black through red and yellow to white at scale
+pub fn heatmap(costs: &[f64], scale: f64, width: u32, height: u32) -> Image {
+    const RAMP: [(f64, f64, f64); 5] = [
+        (0.0, 0.0, 0.0),
+        (0.35, 0.05, 0.4),
+        (0.85, 0.25, 0.15),
+        (1.0, 0.8, 0.25),
+        (1.0, 1.0, 1.0),
+    ];
+
+    let mut image = Image::new(width, height);
+    for (index, &cost) in costs.iter().enumerate() {
+        let t = if scale > 0.0 {
+            (cost / scale).min(1.0)
+        } else {
+            0.0
+        };
+
+        let x = t * (RAMP.len() - 1) as f64;
+        let i = (x as usize).min(RAMP.len() - 2);
+        let f = x - i as f64;
+        let (a, b) = (RAMP[i], RAMP[i + 1]);
+        let color = Color::new(
+            a.0 + f * (b.0 - a.0),
+            a.1 + f * (b.1 - a.1),
+            a.2 + f * (b.2 - a.2),
+        );
+
+        let index = index as u32;
+        image.set(index % width, index / width, color);
+    }
+    image
+}
+
+thread_local! {
+    static COUNTERS: Cell<RayCounters> = const { Cell::new(RayCounters::ZERO) };
+}
+
+pub(crate) fn record(count: impl FnOnce(&mut RayCounters)) {
+    COUNTERS.with(|counters| {
+        let mut current = counters.get();
+        count(&mut current);
+        counters.set(current);
+    });
+}
+
+// Returns what this thread counted since the last call
+pub(crate) fn take() -> RayCounters {
+    COUNTERS.with(|counters| counters.take())
+}
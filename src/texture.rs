@@ -0,0 +1,115 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::image::Image;
+use crate::utils::{self, Color};
+use crate::vec3::Point3;
+
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+
+    // Scalar parameters are read from the channel average
+    fn value_f64(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        self.value(u, v, p).sum() / 3.0
+    }
+}
+
+pub struct SolidColor {
+    albedo: Color,
+}
+
+impl SolidColor {
+    pub fn new(albedo: Color) -> Self {
+        Self { albedo }
+    }
+
+    pub fn scalar(value: f64) -> Self {
+        Self::new(Color::new(value, value, value))
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        self.albedo
+    }
+}
+
+// Solid 3D checker pattern
+pub struct CheckerTexture {
+    inv_scale: f64,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
+        Self {
+            inv_scale: 1.0 / scale,
+            even,
+            odd,
+        }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let sum: i64 = p
+            .xyz()
+            .map(|c| (self.inv_scale * c).floor() as i64)
+            .iter()
+            .sum();
+
+        if sum % 2 == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+pub struct ImageTexture {
+    image: Image,
+}
+
+impl ImageTexture {
+    // Color images are stored sRGB encoded and decoded to linear on load
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut image = Image::read_ppm(path)?;
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let [r, g, b] = image.get(x, y).xyz().map(utils::srgb_to_linear);
+                image.set(x, y, Color::new(r, g, b));
+            }
+        }
+
+        Ok(Self { image })
+    }
+
+    // Data images (roughness, height, normal maps) are used as stored
+    pub fn open_linear(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            image: Image::read_ppm(path)?,
+        })
+    }
+
+    pub fn from_image(image: Image) -> Self {
+        Self { image }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        if self.image.width() == 0 || self.image.height() == 0 {
+            return Color::new(0.0, 1.0, 1.0);
+        }
+
+        let u = u.clamp(0.0, 1.0);
+        let v = 1.0 - v.clamp(0.0, 1.0);
+
+        let i = ((u * self.image.width() as f64) as u32).min(self.image.width() - 1);
+        let j = ((v * self.image.height() as f64) as u32).min(self.image.height() - 1);
+
+        self.image.get(i, j)
+    }
+}
@@ -0,0 +1,153 @@
+use crate::utils::{self, Color};
+
+// Maps scene referred radiance to display referred [0, 1] values, before sRGB encoding
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ToneMap {
+    // Values above 1 are clipped when quantized
+    #[default]
+    Clamp,
+    // Reinhard et al. 2002 on luminance, keeps hue
+    Reinhard,
+    // Same, with luminance white mapped to 1 and brighter values burnt out
+    ExtendedReinhard {
+        white: f64,
+    },
+    // Stephen Hill's fit of the ACES RRT + sRGB ODT
+    Aces,
+    // John Hable's Uncharted 2 filmic curve
+    Hable,
+    // Minimal AgX with the default look
+    AgX,
+}
+
+impl ToneMap {
+    // exposure is in stops, applied before the operator
+    pub fn apply(&self, color: Color, exposure: f64) -> Color {
+        let color = color * exposure.exp2();
+
+        match *self {
+            ToneMap::Clamp => color,
+            ToneMap::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMap::ExtendedReinhard { white } => scale_luminance(color, |l| {
+                (l * (1.0 + l / (white * white)) / (1.0 + l)).min(1.0)
+            }),
+            ToneMap::Aces => aces(color),
+            ToneMap::Hable => hable(color),
+            ToneMap::AgX => agx(color),
+        }
+    }
+}
+
+fn scale_luminance(color: Color, curve: impl Fn(f64) -> f64) -> Color {
+    let l = utils::luminance(color);
+    if l <= 0.0 {
+        return Color::default();
+    }
+    color * (curve(l) / l)
+}
+
+fn mul(m: &[[f64; 3]; 3], c: Color) -> Color {
+    let [r0, r1, r2] = m.map(|row| row[0] * c.x() + row[1] * c.y() + row[2] * c.z());
+    Color::new(r0, r1, r2)
+}
+
+fn map(c: Color, f: impl Fn(f64) -> f64) -> Color {
+    let [r, g, b] = c.xyz().map(f);
+    Color::new(r, g, b)
+}
+
+fn aces(color: Color) -> Color {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    const INPUT: [[f64; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    const OUTPUT: [[f64; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+
+    let rrt_and_odt = |v: f64| {
+        let a = v * (v + 0.0245786) - 0.000090537;
+        let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+        a / b
+    };
+
+    let color = map(mul(&INPUT, color), rrt_and_odt);
+    map(mul(&OUTPUT, color), |c| c.clamp(0.0, 1.0))
+}
+
+fn hable(color: Color) -> Color {
+    const EXPOSURE_BIAS: f64 = 2.0;
+    const WHITE: f64 = 11.2;
+
+    let curve = |x: f64| {
+        let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+    };
+
+    let white_scale = 1.0 / curve(WHITE);
+    map(color, |c| {
+        (curve(EXPOSURE_BIAS * c.max(0.0)) * white_scale).min(1.0)
+    })
+}
+
+fn agx(color: Color) -> Color {
+    const INSET: [[f64; 3]; 3] = [
+        [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+        [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+        [0.0423756549057051, 0.0784336, 0.879142973793104],
+    ];
+    const OUTSET: [[f64; 3]; 3] = [
+        [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+        [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+        [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+    ];
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+
+    // Polynomial fit of the default contrast sigmoid on the log encoded values
+    let contrast = |x: f64| {
+        let (x2, x4) = (x * x, x * x * x * x);
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    };
+
+    let color = map(mul(&INSET, color), |c| {
+        let ev = c.max(1e-10).log2().clamp(MIN_EV, MAX_EV);
+        contrast((ev - MIN_EV) / (MAX_EV - MIN_EV))
+    });
+
+    // The sigmoid outputs display encoded values, back to linear for the sRGB transfer
+    map(mul(&OUTSET, color), |c| c.clamp(0.0, 1.0).powf(2.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monotonic_and_in_range() {
+        let operators = [
+            ToneMap::Reinhard,
+            ToneMap::ExtendedReinhard { white: 4.0 },
+            ToneMap::Aces,
+            ToneMap::Hable,
+            ToneMap::AgX,
+        ];
+
+        for op in operators {
+            let mut prev = 0.0;
+            for i in 0..=200 {
+                let x = 0.001 * 1.05f64.powi(i);
+                let y = utils::luminance(op.apply(Color::new(x, x, x), 0.0));
+                assert!((0.0..=1.0 + 1e-6).contains(&y), "{op:?}({x}) = {y}");
+                assert!(y >= prev - 1e-9, "{op:?} isn't monotonic at {x}");
+                prev = y;
+            }
+        }
+    }
+}
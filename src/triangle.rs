@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::{HitRecord, Hittable};
+use crate::stats;
+use crate::vec3::{Point3, Vec3};
+
+pub struct Triangle {
+    vertices: [Point3; 3],
+    uvs: [(f64, f64); 3],
+    normal: Vec3,
+    tangent: Vec3,
+    mat: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(vertices: [Point3; 3], mat: Arc<dyn Material>) -> Self {
+        Self::with_uvs(vertices, [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)], mat)
+    }
+
+    pub fn with_uvs(vertices: [Point3; 3], uvs: [(f64, f64); 3], mat: Arc<dyn Material>) -> Self {
+        let [v0, v1, v2] = vertices;
+        let e1 = v1 - v0;
+        let e2 = v2 - v0;
+
+        // dp/du from the uv parameterization, falling back to an edge when it's degenerate
+        let (du1, dv1) = (uvs[1].0 - uvs[0].0, uvs[1].1 - uvs[0].1);
+        let (du2, dv2) = (uvs[2].0 - uvs[0].0, uvs[2].1 - uvs[0].1);
+        let det = du1 * dv2 - du2 * dv1;
+        let tangent = if det.abs() < 1e-12 {
+            e1
+        } else {
+            (dv2 * e1 - dv1 * e2) / det
+        };
+
+        Self {
+            vertices,
+            uvs,
+            normal: e1.cross(&e2).unit(),
+            tangent,
+            mat,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    // Möller-Trumbore
+    fn hit(&self, r: &Ray, ray_tmin: f64, ray_tmax: f64) -> Option<HitRecord> {
+        stats::record(|counters| counters.triangle_tests += 1);
+
+        let [v0, v1, v2] = self.vertices;
+        let e1 = v1 - v0;
+        let e2 = v2 - v0;
+
+        let pvec = r.direction().cross(&e2);
+        let det = e1.dot(&pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = r.origin() - v0;
+        let b1 = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+
+        let qvec = tvec.cross(&e1);
+        let b2 = r.direction().dot(&qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+
+        let t = e2.dot(&qvec) * inv_det;
+        if !(ray_tmin..=ray_tmax).contains(&t) {
+            return None;
+        }
+
+        let b0 = 1.0 - b1 - b2;
+        let [uv0, uv1, uv2] = self.uvs;
+
+        let mut rec = HitRecord {
+            t,
+            p: r.at(t),
+            mat: self.mat.clone(),
+            normal: Default::default(),
+            tangent: Default::default(),
+            bitangent: Default::default(),
+            u: b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+            v: b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+            front_face: Default::default(),
+            barycentric: (b1, b2),
+            object_id: Default::default(),
+            material_id: Default::default(),
+        };
+
+        rec.set_face_normal(r, self.normal);
+        rec.set_tangent(self.tangent);
+
+        Some(rec)
+    }
+}
@@ -1,15 +1,16 @@
-use rand::Rng;
 use std::f64::consts::PI;
 use std::io::Write;
 
+use crate::sampler;
 use crate::vec3::Vec3;
 
 pub fn deg_to_rad(deg: f64) -> f64 {
     deg * PI / 180.0
 }
 
+// Next dimension of the installed sampler, see `sampler::install`
 pub fn rand_f64() -> f64 {
-    rand::thread_rng().gen()
+    sampler::get_1d()
 }
 
 pub fn rand_range_f64(min: f64, max: f64) -> f64 {
@@ -20,17 +21,32 @@ pub fn rand_range_f64(min: f64, max: f64) -> f64 {
 
 pub type Color = Vec3;
 
-pub fn linear_to_gamma(linear_component: f64) -> f64 {
-    if linear_component > 0.0 {
-        linear_component.sqrt()
+// sRGB transfer function (IEC 61966-2-1)
+pub fn linear_to_srgb(linear_component: f64) -> f64 {
+    if linear_component <= 0.0031308 {
+        12.92 * linear_component.max(0.0)
     } else {
-        0.0
+        1.055 * linear_component.powf(1.0 / 2.4) - 0.055
     }
 }
 
+pub fn srgb_to_linear(srgb_component: f64) -> f64 {
+    if srgb_component <= 0.04045 {
+        srgb_component / 12.92
+    } else {
+        ((srgb_component + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Rec. 709 relative luminance
+pub fn luminance(color: Color) -> f64 {
+    color.dot(&Color::new(0.2126, 0.7152, 0.0722))
+}
+
+// pixel_color is display referred, see `tonemap::ToneMap`
 pub fn write_color(out: &mut impl Write, pixel_color: Color) {
     let [r, g, b] = pixel_color
         .xyz()
-        .map(|c| (256.0 * linear_to_gamma(c).clamp(0.000, 0.999)) as i32);
+        .map(|c| (256.0 * linear_to_srgb(c).clamp(0.000, 0.999)) as i32);
     writeln!(out, "{} {} {}", r, g, b).expect("writing color");
 }
@@ -1,5 +1,7 @@
+use crate::sampler;
 use crate::utils::{rand_f64, rand_range_f64};
 
+use std::f64::consts::PI;
 use std::fmt::{Display, Formatter, Result};
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub};
 
@@ -68,13 +70,13 @@ impl Vec3 {
     }
 
     pub fn rand_unit_vec() -> Vec3 {
-        loop {
-            let p = Vec3::rand_range(-1.0, 1.0);
-            let len_sq = p.len_sq();
-            if 1e-160 < len_sq && len_sq <= 1.0 {
-                return p / len_sq.sqrt();
-            }
-        }
+        let (u1, u2) = sampler::get_2d();
+
+        let z = 1.0 - 2.0 * u1;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
     }
 
     pub fn rand_vec_on_hemisphere(normal: &Vec3) -> Vec3 {
@@ -86,6 +88,16 @@ impl Vec3 {
         }
     }
 
+    // Cosine weighted direction around +z
+    pub fn rand_cosine_direction() -> Vec3 {
+        let (r1, r2) = sampler::get_2d();
+
+        let phi = 2.0 * PI * r1;
+        let r = r2.sqrt();
+
+        Vec3::new(phi.cos() * r, phi.sin() * r, (1.0 - r2).sqrt())
+    }
+
     pub fn is_near_zero(&self) -> bool {
         const EPS: f64 = 1.0E-8;
         let [x, y, z] = self.xyz();
@@ -98,20 +110,27 @@ impl Vec3 {
     }
 
     pub fn refract(&self, normal: &Vec3, etai_over_etat: f64) -> Vec3 {
-        let cos_theta = f64::min(-self.dot(&normal), 1.0);
+        let cos_theta = f64::min(-self.dot(normal), 1.0);
         let r_out_perp = etai_over_etat * (*self + cos_theta * *normal);
         let r_out_parallel = -f64::sqrt(f64::abs(1.0 - r_out_perp.len_sq())) * *normal;
         r_out_perp + r_out_parallel
     }
 
+    // Shirley-Chiu concentric mapping, keeps the sample's stratification
     pub fn rand_in_unit_disk() -> Vec3 {
-        loop {
-            let p = Vec3::new(rand_range_f64(-1.0, 1.0), rand_range_f64(-1.0, 1.0), 0.0);
-            if p.len_sq() >= 1.0 {
-                continue;
-            }
-            return p;
+        let (u1, u2) = sampler::get_2d();
+        let (x, y) = (2.0 * u1 - 1.0, 2.0 * u2 - 1.0);
+        if x == 0.0 && y == 0.0 {
+            return Vec3::default();
         }
+
+        let (r, theta) = if x.abs() > y.abs() {
+            (x, PI / 4.0 * (y / x))
+        } else {
+            (y, PI / 2.0 - PI / 4.0 * (x / y))
+        };
+
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
     }
 }
 